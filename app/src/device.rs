@@ -0,0 +1,158 @@
+use gst::prelude::*;
+
+use lazy_static::lazy_static;
+
+use std::fs;
+
+lazy_static! {
+    /// Keeps running so that hotplugged devices show up in the lists.
+    static ref MONITOR: gst::DeviceMonitor = {
+        gst::init().unwrap();
+        let monitor = gst::DeviceMonitor::new();
+        monitor.add_filter(Some("Video/Source"), None);
+        monitor.add_filter(Some("Audio/Source"), None);
+        // Nobody reads the hotplug messages, the device list is polled instead
+        monitor.bus().set_sync_handler(|_, _| gst::BusSyncReply::Drop);
+        if let Err(err) = monitor.start() {
+            println!("Failed to start device monitor: {}", err);
+        }
+        monitor
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    Video,
+    Audio,
+}
+
+impl DeviceKind {
+    fn class(&self) -> &'static str {
+        match self {
+            DeviceKind::Video => "Video/Source",
+            DeviceKind::Audio => "Audio/Source",
+        }
+    }
+}
+
+/// Capture device as discovered by the device monitor. The id does not change
+/// between boots: the bus path for V4L2 and the card name for ALSA.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureDevice {
+    pub id: String,
+    pub name: String,
+    pub caps: String,
+    /// What the source element's `device` property has to be set to
+    pub device: String,
+}
+
+impl std::fmt::Display for CaptureDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.name.is_empty(), self.caps.is_empty()) {
+            (true, _) => write!(f, "{}", self.id),
+            (false, true) => write!(f, "{}", self.name),
+            (false, false) => write!(f, "{} ({})", self.name, self.caps),
+        }
+    }
+}
+
+impl CaptureDevice {
+    /// Placeholder for a configured device that is not plugged in.
+    pub fn from_id(id: &str) -> CaptureDevice {
+        CaptureDevice {
+            id: id.to_string(),
+            name: String::new(),
+            caps: String::new(),
+            device: id.to_string(),
+        }
+    }
+
+    /// Pick list entry for going back to the default ALSA device. Its empty id
+    /// is stored as no device at all.
+    pub fn default_audio() -> CaptureDevice {
+        CaptureDevice {
+            id: String::new(),
+            name: String::from("Default"),
+            caps: String::new(),
+            device: String::new(),
+        }
+    }
+
+    pub fn list(kind: DeviceKind) -> Vec<CaptureDevice> {
+        MONITOR
+            .devices()
+            .iter()
+            .filter(|device| device.has_classes(kind.class()))
+            .filter_map(|device| CaptureDevice::from_device(device, kind))
+            .collect()
+    }
+
+    /// Turns a configured id into the value of the source's `device` property.
+    /// Node paths and ALSA names are passed through as they are.
+    pub fn resolve(id: &str, kind: DeviceKind) -> String {
+        Self::list(kind)
+            .into_iter()
+            .find(|device| device.id == id)
+            .map_or_else(|| id.to_string(), |device| device.device)
+    }
+
+    fn from_device(device: &gst::Device, kind: DeviceKind) -> Option<CaptureDevice> {
+        let props = device.properties()?;
+        let property = |name: &str| -> Option<String> {
+            let value = props.value(name).ok()?.transform::<String>().ok()?;
+            value.get::<Option<String>>().ok().flatten()
+        };
+
+        let (id, path) = match kind {
+            DeviceKind::Video => {
+                let path = property("device.path")?;
+                let id = property("device.bus_path")
+                    .or_else(|| property("v4l2.device.bus_info"))
+                    .unwrap_or_else(|| path.clone());
+                (id, path)
+            }
+            DeviceKind::Audio => {
+                // hw:N,M depends on the probe order, the card name does not
+                if property("device.api")? != "alsa" {
+                    return None;
+                }
+                let card = property("alsa.card")?;
+                let element = device.create_element(None).ok()?;
+                let hw = element.property::<Option<String>>("device")?;
+                let pcm = hw.rsplit(',').next()?.to_string();
+                let card_id = fs::read_to_string(format!("/proc/asound/card{}/id", card)).ok()?;
+                let id = format!("plughw:CARD={},DEV={}", card_id.trim(), pcm);
+                (id.clone(), id)
+            }
+        };
+
+        Some(CaptureDevice {
+            id,
+            name: device.display_name().to_string(),
+            caps: device.caps().map(|caps| summary(&caps)).unwrap_or_default(),
+            device: path,
+        })
+    }
+}
+
+/// Short description of the supported formats, such as `1920x1080, 1280x720`.
+fn summary(caps: &gst::Caps) -> String {
+    let mut formats: Vec<String> = vec![];
+    for structure in caps.iter() {
+        let format = match (
+            structure.get::<i32>("width"),
+            structure.get::<i32>("height"),
+            structure.get::<i32>("channels"),
+            structure.get::<i32>("rate"),
+        ) {
+            (Ok(width), Ok(height), _, _) => format!("{}x{}", width, height),
+            (_, _, Ok(channels), Ok(rate)) => format!("{}ch {}Hz", channels, rate),
+            _ => continue,
+        };
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+    formats.truncate(3);
+    formats.join(", ")
+}
@@ -1,5 +1,8 @@
 use iced::Font;
 
+use std::env;
+use std::path::PathBuf;
+
 pub const ICONS: Font = Font::External {
     name: "Font Awesome Solid",
     bytes: include_bytes!("../font/fa-solid-900.ttf"),
@@ -20,6 +23,27 @@ pub const PLEXMONO: Font = Font::External {
     bytes: include_bytes!("../font/IBMPlexMono-Regular.ttf"),
 };
 
+/// Makes the bundled Plex fonts available to Pango, which renders text overlays
+/// in the pipeline and only sees fonts known to fontconfig.
+pub fn install() -> Result<(), std::io::Error> {
+    let dir = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .map(|path| path.join("fonts/broadcast-terminal"))
+        .ok_or(std::io::ErrorKind::NotFound)?;
+    std::fs::create_dir_all(&dir)?;
+
+    for font in [PLEXSANS, PLEXSANSBOLD, PLEXMONO] {
+        if let Font::External { name, bytes } = font {
+            let path = dir.join(format!("{}.ttf", name.replace(' ', "")));
+            if !path.exists() {
+                std::fs::write(path, bytes)?;
+            }
+        }
+    }
+    Ok(())
+}
+
 pub enum Icon {
     Gear,
     Gauge,
@@ -30,6 +54,7 @@ pub enum Icon {
     Microphone,
     VolumeOff,
     Shuffle,
+    CircleDot,
 }
 
 impl ToString for Icon {
@@ -44,6 +69,7 @@ impl ToString for Icon {
             Icon::Microphone => '\u{f130}',
             Icon::VolumeOff => '\u{f026}',
             Icon::Shuffle => '\u{f074}',
+            Icon::CircleDot => '\u{f192}',
         }
         .to_string()
     }
@@ -1,5 +1,8 @@
+use derive_more::{Display, Error};
 use serde::{Deserialize, Serialize};
 
+use crate::setting::Destination;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct TwitchIngest {
     pub name: String,
@@ -35,16 +38,47 @@ impl Default for Service {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Display, Error)]
 pub enum IngestError {
+    #[display(fmt = "Invalid ingest setting")]
     InvalidSetting,
+    #[display(fmt = "Unsupported ingest protocol")]
+    UnsupportedProtocol,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Rtmp,
+    Rtmps,
+    Srt,
+}
+
+impl Protocol {
+    pub fn from_url(url: &str) -> Result<Protocol, IngestError> {
+        match url.split("://").next() {
+            Some("rtmp") => Ok(Protocol::Rtmp),
+            Some("rtmps") => Ok(Protocol::Rtmps),
+            Some("srt") => Ok(Protocol::Srt),
+            _ => Err(IngestError::UnsupportedProtocol),
+        }
+    }
 }
 
 impl Service {
-    pub async fn get_ingest_url() -> Result<String, IngestError> {
-        let service = crate::SETTINGS.read().unwrap().broadcast.ingest_service;
-        if let Some(service) = service {
-            Ok(match service {
+    pub async fn get_ingest_url(index: usize) -> Result<String, IngestError> {
+        let destination = crate::SETTINGS
+            .read()
+            .unwrap()
+            .broadcast
+            .destinations()
+            .get(index)
+            .cloned();
+        match destination {
+            Some(Destination {
+                ingest_service: Some(service),
+                custom_url,
+                ..
+            }) => Ok(match service {
                 Service::YouTubeLive => "rtmp://a.rtmp.youtube.com/live2/{stream_key}".to_string(),
                 Service::Twitch => Twitch::get_ingests()
                     .await
@@ -54,10 +88,9 @@ impl Service {
                     .unwrap()
                     .url_template
                     .clone(),
-                Service::Custom => crate::SETTINGS.read().unwrap().broadcast.custom_url.clone(),
-            })
-        } else {
-            Err(IngestError::InvalidSetting)
+                Service::Custom => custom_url,
+            }),
+            _ => Err(IngestError::InvalidSetting),
         }
     }
 }
@@ -2,6 +2,7 @@ use iced::{executor, window, Application, Command, Element, Settings, Subscripti
 
 use std::env;
 
+mod device;
 mod font;
 mod ingest;
 mod setting;
@@ -41,6 +42,7 @@ pub fn main() -> iced::Result {
 struct App {
     control: view::control::App,
     setting: view::setting::App,
+    microphone: view::microphone::App,
     view: View,
 }
 
@@ -58,12 +60,14 @@ impl Application for App {
     fn new(_flags: ()) -> (App, Command<Self::Message>) {
         let control = view::control::App::new();
         let setting = view::setting::App::new();
+        let microphone = view::microphone::App::new();
         let view = View::Control;
 
         (
             App {
                 control,
                 setting,
+                microphone,
                 view,
             },
             Command::none(),
@@ -95,6 +99,11 @@ impl Application for App {
                 .subscription()
                 .map(view::ViewMessage::Setting)
                 .map(crate::Message::ViewMessage),
+            View::Microphone => self
+                .microphone
+                .subscription()
+                .map(view::ViewMessage::Microphone)
+                .map(crate::Message::ViewMessage),
         }
     }
 
@@ -106,13 +115,19 @@ impl Application for App {
                     View::Control => {
                         self.control.reload_setting();
                     }
-                    _ => {}
+                    View::Setting => {
+                        self.setting.refresh();
+                    }
+                    View::Microphone => {
+                        self.microphone.refresh();
+                    }
                 }
                 Command::none()
             }
             Message::ViewMessage(message) => match message {
                 view::ViewMessage::Control(message) => self.control.update(message),
                 view::ViewMessage::Setting(message) => self.setting.update(message),
+                view::ViewMessage::Microphone(message) => self.microphone.update(message),
             },
         }
     }
@@ -121,6 +136,7 @@ impl Application for App {
         match self.view {
             View::Control => self.control.view(),
             View::Setting => self.setting.view(),
+            View::Microphone => self.microphone.view(),
         }
     }
 }
@@ -1,22 +1,177 @@
 use crate::ingest::Service;
 use serde::{Deserialize, Serialize};
 
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct Destination {
+    pub ingest_service: Option<Service>,
+    #[serde(default)]
+    pub custom_url: String,
+    #[serde(default)]
+    pub stream_key: String,
+}
 
 #[derive(Deserialize, Serialize, Default, Debug)]
 pub struct BroadcastSetting {
     pub ingest_service: Option<Service>,
     pub custom_url: String,
     pub stream_key: String,
+    #[serde(default)]
+    pub destinations: Vec<Destination>,
+}
+
+impl BroadcastSetting {
+    /// The main destination followed by additional simulcast destinations.
+    pub fn destinations(&self) -> Vec<Destination> {
+        let main = Destination {
+            ingest_service: self.ingest_service,
+            custom_url: self.custom_url.clone(),
+            stream_key: self.stream_key.clone(),
+        };
+        std::iter::once(main)
+            .chain(self.destinations.iter().cloned())
+            .collect()
+    }
 }
 
 #[derive(Deserialize, Serialize, Default, Debug)]
 pub struct DeviceSetting {
     pub hdmi_device: Option<String>,
     pub camera_device: Option<String>,
+    pub hdmi_audio_device: Option<String>,
+    pub mic_device: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct CaptureSetting {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub framerate: Option<u32>,
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PipPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Custom,
+}
+
+impl PipPosition {
+    pub const ALL: [PipPosition; 5] = [
+        PipPosition::TopLeft,
+        PipPosition::TopRight,
+        PipPosition::BottomLeft,
+        PipPosition::BottomRight,
+        PipPosition::Custom,
+    ];
+}
+
+impl Default for PipPosition {
+    fn default() -> PipPosition {
+        PipPosition::TopLeft
+    }
+}
+
+impl std::fmt::Display for PipPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                PipPosition::TopLeft => "Top Left",
+                PipPosition::TopRight => "Top Right",
+                PipPosition::BottomLeft => "Bottom Left",
+                PipPosition::BottomRight => "Bottom Right",
+                PipPosition::Custom => "Custom",
+            }
+        )
+    }
+}
+
+impl std::str::FromStr for PipPosition {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+    }
+}
+
+/// Picture-in-picture placement of the camera, in program pixels.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct CameraSetting {
+    pub position: Option<PipPosition>,
+    /// Distance from the edges for the corner presets
+    pub margin: Option<u32>,
+    /// Top-left corner for the custom position
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: Option<u32>,
+    pub border: Option<u32>,
+    /// Border color as 0xAARRGGBB
+    pub border_color: Option<u32>,
+    pub opacity: Option<f64>,
+}
+
+/// Still image such as a logo or watermark, composited at its native size times `scale`.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct ImageSetting {
+    /// Name used to refer to the image from scenes
+    pub name: String,
+    pub path: String,
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub scale: Option<f64>,
+    pub opacity: Option<f64>,
+}
+
+/// Text such as a title or lower-third, drawn on a box of `background` color.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct TextSetting {
+    /// Name used to refer to the text from scenes
+    pub name: String,
+    pub text: Option<String>,
+    /// File to take the text from whenever it changes
+    pub file: Option<String>,
+    /// Pango font description, defaults to IBM Plex Sans Bold
+    pub font: Option<String>,
+    pub size: Option<u32>,
+    /// Text color as 0xAARRGGBB
+    pub color: Option<u32>,
+    /// Box color as 0xAARRGGBB, transparent by default
+    pub background: Option<u32>,
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// Where a source appears in a scene. Unset fields keep the default layout of the source.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct Placement {
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub alpha: Option<f64>,
+}
+
+/// Named set of visible sources. Sources that are not listed are hidden.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct Scene {
+    pub name: String,
+    /// Turns ducking on or off while the scene is active
+    pub ducking: Option<bool>,
+    #[serde(default)]
+    pub sources: BTreeMap<String, Placement>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -33,16 +188,275 @@ impl std::str::FromStr for MicrophoneMode {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NoiseSuppressionLevel {
+    Low,
+    Moderate,
+    High,
+    VeryHigh,
+}
+
+impl NoiseSuppressionLevel {
+    pub const ALL: [NoiseSuppressionLevel; 4] = [
+        NoiseSuppressionLevel::Low,
+        NoiseSuppressionLevel::Moderate,
+        NoiseSuppressionLevel::High,
+        NoiseSuppressionLevel::VeryHigh,
+    ];
+}
+
+impl Default for NoiseSuppressionLevel {
+    fn default() -> NoiseSuppressionLevel {
+        NoiseSuppressionLevel::Moderate
+    }
+}
+
+impl std::fmt::Display for NoiseSuppressionLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                NoiseSuppressionLevel::Low => "Low",
+                NoiseSuppressionLevel::Moderate => "Moderate",
+                NoiseSuppressionLevel::High => "High",
+                NoiseSuppressionLevel::VeryHigh => "Very High",
+            }
+        )
+    }
+}
+
+/// Optional processing stages of the microphone, applied in this order.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct MicProcessing {
+    #[serde(default)]
+    pub noise_suppression: bool,
+    pub noise_suppression_level: Option<NoiseSuppressionLevel>,
+    #[serde(default)]
+    pub gate: bool,
+    /// Gate threshold in dBFS
+    pub gate_threshold: Option<f64>,
+    #[serde(default)]
+    pub compressor: bool,
+    /// Compressor threshold in dBFS
+    pub compressor_threshold: Option<f64>,
+    pub compressor_ratio: Option<f64>,
+}
+
+impl MicProcessing {
+    pub fn gate_threshold(&self) -> f64 {
+        self.gate_threshold.unwrap_or(-50.0)
+    }
+
+    pub fn compressor_threshold(&self) -> f64 {
+        self.compressor_threshold.unwrap_or(-18.0)
+    }
+
+    pub fn compressor_ratio(&self) -> f64 {
+        self.compressor_ratio.unwrap_or(4.0)
+    }
+}
+
+/// Lowers the HDMI audio while the microphone is above the threshold.
+#[derive(Deserialize, Serialize, Default, Debug, Clone)]
+pub struct Ducking {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Microphone level in dBFS
+    pub threshold: Option<f64>,
+    /// Reduction of the HDMI audio in dB
+    pub amount: Option<f64>,
+    /// Attack and release times in ms
+    pub attack: Option<u64>,
+    pub release: Option<u64>,
+}
+
+impl Ducking {
+    pub fn threshold(&self) -> f64 {
+        self.threshold.unwrap_or(-40.0)
+    }
+
+    pub fn amount(&self) -> f64 {
+        self.amount.unwrap_or(12.0)
+    }
+
+    pub fn attack(&self) -> Duration {
+        Duration::from_millis(self.attack.unwrap_or(50))
+    }
+
+    pub fn release(&self) -> Duration {
+        Duration::from_millis(self.release.unwrap_or(500))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum H264Encoder {
+    V4l2,
+    X264,
+    OpenH264,
+}
+
+impl std::str::FromStr for H264Encoder {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum H264Profile {
+    Baseline,
+    Main,
+    High,
+}
+
+impl std::str::FromStr for H264Profile {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct EncodingSetting {
+    pub encoder: Option<H264Encoder>,
+    /// Video bitrate in kbit/s
+    pub bitrate: Option<u32>,
+    /// Keyframe interval in seconds
+    pub keyframe_interval: Option<u32>,
+    pub profile: Option<H264Profile>,
+    pub level: Option<String>,
+    pub framerate: Option<u32>,
+    /// Audio bitrate in kbit/s
+    pub audio_bitrate: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compositor {
+    Gl,
+    Software,
+}
+
+impl std::str::FromStr for Compositor {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+    }
+}
+
 #[derive(Deserialize, Serialize, Default, Debug)]
 pub struct MediaSetting {
     pub mic_mode: Option<MicrophoneMode>,
+    pub compositor: Option<Compositor>,
+    /// HDMI audio gain in dB
+    pub hdmi_gain: Option<f64>,
+    pub hdmi_mute: Option<bool>,
+    /// Microphone gain in dB
+    pub mic_gain: Option<f64>,
+    pub mic_mute: Option<bool>,
+    /// A/V sync delays in ms, negative values make the source earlier
+    pub hdmi_delay: Option<i64>,
+    pub mic_delay: Option<i64>,
+    pub video_delay: Option<i64>,
+    #[serde(default)]
+    pub mic_processing: MicProcessing,
+    #[serde(default)]
+    pub ducking: Ducking,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RecordingContainer {
+    Matroska,
+    Mp4,
+    FragmentedMp4,
+}
+
+impl RecordingContainer {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordingContainer::Matroska => "mkv",
+            RecordingContainer::Mp4 | RecordingContainer::FragmentedMp4 => "mp4",
+        }
+    }
+}
+
+impl Default for RecordingContainer {
+    fn default() -> RecordingContainer {
+        RecordingContainer::Matroska
+    }
+}
+
+impl std::str::FromStr for RecordingContainer {
+    type Err = serde_json::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct RecordingSetting {
+    pub directory: Option<String>,
+    pub container: Option<RecordingContainer>,
+    /// Length of the replay buffer in seconds, no buffer when unset
+    pub replay: Option<u64>,
+}
+
+impl RecordingSetting {
+    pub fn directory(&self) -> PathBuf {
+        self.directory
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join("Videos")))
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+}
+
+/// Level meter thresholds in dBFS and clip logging.
+#[derive(Deserialize, Serialize, Default, Debug)]
+pub struct MeterSetting {
+    pub warning: Option<f32>,
+    pub clip: Option<f32>,
+    /// Print the time of every clip event
+    #[serde(default)]
+    pub log_clips: bool,
+}
+
+impl MeterSetting {
+    pub fn warning(&self) -> f32 {
+        self.warning.unwrap_or(-18.0)
+    }
+
+    pub fn clip(&self) -> f32 {
+        self.clip.unwrap_or(-3.0)
+    }
 }
 
 #[derive(Deserialize, Serialize, Default, Debug)]
 pub struct Settings {
+    // toml can only write plain values and arrays before the first table
+    #[serde(default)]
+    pub images: Vec<ImageSetting>,
+    #[serde(default)]
+    pub texts: Vec<TextSetting>,
+    #[serde(default)]
+    pub scenes: Vec<Scene>,
     pub broadcast: BroadcastSetting,
     pub device: DeviceSetting,
+    #[serde(default)]
+    pub capture: CaptureSetting,
+    #[serde(default)]
+    pub camera: CameraSetting,
     pub media: MediaSetting,
+    #[serde(default)]
+    pub encoding: EncodingSetting,
+    #[serde(default)]
+    pub recording: RecordingSetting,
+    #[serde(default)]
+    pub meter: MeterSetting,
 }
 
 impl Settings {
@@ -60,12 +474,15 @@ impl Settings {
             .ok_or(std::io::ErrorKind::NotFound.into())
     }
 
+    /// Reads the config file as it is, without the environment overrides.
+    fn load() -> Result<Self, std::io::Error> {
+        let config = std::fs::read_to_string(Self::config_file()?)?;
+        toml::from_str(&config)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
     pub fn new() -> Self {
-        let setting: Self = Self::config_file()
-            .and_then(std::fs::read_to_string)
-            .ok()
-            .and_then(|s| toml::from_str(&s).ok())
-            .unwrap_or_default();
+        let setting: Self = Self::load().unwrap_or_default();
 
         println!("Load setting: {:?}", &setting);
 
@@ -75,6 +492,23 @@ impl Settings {
         let camera_device = env::var("CAMERA_DEVICE")
             .ok()
             .or(setting.device.camera_device);
+        let hdmi_audio_device = env::var("HDMI_AUDIO_DEVICE")
+            .ok()
+            .or(setting.device.hdmi_audio_device);
+        let mic_device = env::var("MIC_DEVICE").ok().or(setting.device.mic_device);
+        let capture_size: Option<(u32, u32)> = env::var("CAPTURE_SIZE").ok().and_then(|size| {
+            let (width, height) = size.split_once('x')?;
+            Some((width.parse().ok()?, height.parse().ok()?))
+        });
+        let capture_framerate = env::var("CAPTURE_FRAMERATE")
+            .ok()
+            .and_then(|framerate| framerate.parse().ok())
+            .or(setting.capture.framerate);
+        let capture_format = env::var("CAPTURE_FORMAT").ok().or(setting.capture.format);
+        let camera_position = env::var("CAMERA_POSITION")
+            .ok()
+            .and_then(|position| position.parse().ok())
+            .or(setting.camera.position);
         let ingest_service = env::var("INGEST_SERVICE")
             .ok()
             .and_then(|service| service.parse().ok())
@@ -84,32 +518,215 @@ impl Settings {
             .ok()
             .and_then(|mode| mode.parse().ok())
             .or(setting.media.mic_mode);
+        let compositor = env::var("COMPOSITOR")
+            .ok()
+            .and_then(|compositor| compositor.parse().ok())
+            .or(setting.media.compositor);
+
+        let encoder = env::var("VIDEO_ENCODER")
+            .ok()
+            .and_then(|encoder| encoder.parse().ok())
+            .or(setting.encoding.encoder);
+        let bitrate = env::var("VIDEO_BITRATE")
+            .ok()
+            .and_then(|bitrate| bitrate.parse().ok())
+            .or(setting.encoding.bitrate);
+        let keyframe_interval = env::var("KEYFRAME_INTERVAL")
+            .ok()
+            .and_then(|interval| interval.parse().ok())
+            .or(setting.encoding.keyframe_interval);
+        let profile = env::var("H264_PROFILE")
+            .ok()
+            .and_then(|profile| profile.parse().ok())
+            .or(setting.encoding.profile);
+        let level = env::var("H264_LEVEL").ok().or(setting.encoding.level);
+        let framerate = env::var("FRAMERATE")
+            .ok()
+            .and_then(|framerate| framerate.parse().ok())
+            .or(setting.encoding.framerate);
+        let audio_bitrate = env::var("AUDIO_BITRATE")
+            .ok()
+            .and_then(|bitrate| bitrate.parse().ok())
+            .or(setting.encoding.audio_bitrate);
+
+        let recording_directory = env::var("RECORDING_DIR")
+            .ok()
+            .or(setting.recording.directory);
+        let recording_container = env::var("RECORDING_CONTAINER")
+            .ok()
+            .and_then(|container| container.parse().ok())
+            .or(setting.recording.container);
+        let replay = env::var("REPLAY_DURATION")
+            .ok()
+            .and_then(|duration| duration.parse().ok())
+            .or(setting.recording.replay);
 
         Settings {
             broadcast: BroadcastSetting {
                 ingest_service,
                 custom_url,
                 stream_key,
+                destinations: setting.broadcast.destinations,
             },
             device: DeviceSetting {
                 hdmi_device,
                 camera_device,
+                hdmi_audio_device,
+                mic_device,
+            },
+            capture: CaptureSetting {
+                width: capture_size.map(|size| size.0).or(setting.capture.width),
+                height: capture_size.map(|size| size.1).or(setting.capture.height),
+                framerate: capture_framerate,
+                format: capture_format,
+            },
+            camera: CameraSetting {
+                position: camera_position,
+                ..setting.camera
+            },
+            media: MediaSetting {
+                mic_mode,
+                compositor,
+                ..setting.media
+            },
+            encoding: EncodingSetting {
+                encoder,
+                bitrate,
+                keyframe_interval,
+                profile,
+                level,
+                framerate,
+                audio_bitrate,
             },
-            media: MediaSetting { mic_mode },
+            recording: RecordingSetting {
+                directory: recording_directory,
+                container: recording_container,
+                replay,
+            },
+            meter: setting.meter,
+            images: setting.images,
+            texts: setting.texts,
+            scenes: setting.scenes,
         }
     }
 
     pub fn save(&self) -> Result<(), std::io::Error> {
         let config_file = Self::config_file()?;
+        let toml = toml::to_string(self)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
 
+        // Never leave a truncated config behind
+        let temp_file = config_file.with_extension("toml.tmp");
         OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
-            .open(config_file)
-            .and_then(|mut file| {
-                let toml = toml::to_string(self).unwrap();
-                write!(file, "{}", toml)
-            })
+            .open(&temp_file)
+            .and_then(|mut file| write!(file, "{}", toml))?;
+        std::fs::rename(temp_file, config_file)
+    }
+
+    /// Stores the gain and mute of both sources. The rest of the file is kept
+    /// as it is, so that environment overrides don't end up in it.
+    pub fn save_gain(hdmi: (f64, bool), mic: (f64, bool)) -> Result<(), std::io::Error> {
+        let mut setting = match Self::load() {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            setting => setting?,
+        };
+        setting.media.hdmi_gain = Some(hdmi.0);
+        setting.media.hdmi_mute = Some(hdmi.1);
+        setting.media.mic_gain = Some(mic.0);
+        setting.media.mic_mute = Some(mic.1);
+        setting.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Saves and reloads the config, returning what was reloaded.
+    fn round_trip(config: &str) -> Settings {
+        let settings: Settings = toml::from_str(config).unwrap();
+        let saved = toml::to_string(&settings).unwrap();
+        let reloaded: Settings = toml::from_str(&saved).unwrap();
+        assert_eq!(saved, toml::to_string(&reloaded).unwrap());
+        reloaded
+    }
+
+    #[test]
+    fn round_trip_minimal() {
+        let settings = round_trip(
+            r#"
+            [broadcast]
+            custom_url = ""
+            stream_key = ""
+
+            [device]
+
+            [media]
+            "#,
+        );
+        assert!(settings.images.is_empty());
+        assert!(settings.scenes.is_empty());
+        assert!(!settings.media.ducking.enabled);
+        assert_eq!(settings.recording.replay, None);
+    }
+
+    #[test]
+    fn round_trip_sources_and_scenes() {
+        let settings = round_trip(
+            r#"
+            [[images]]
+            name = "logo"
+            path = "/tmp/logo.png"
+
+            [[texts]]
+            name = "title"
+            text = "Hello"
+
+            [[scenes]]
+            name = "Screen"
+            ducking = false
+            [scenes.sources.hdmi]
+            [scenes.sources.camera]
+            x = 0
+            width = 640
+
+            [broadcast]
+            custom_url = "rtmp://localhost/live"
+            stream_key = "key"
+
+            [device]
+            hdmi_device = "/dev/video0"
+
+            [media]
+            hdmi_gain = -3.0
+
+            [media.ducking]
+            enabled = true
+
+            [recording]
+            replay = 30
+
+            [meter]
+            log_clips = true
+            "#,
+        );
+        assert_eq!(settings.images[0].name, "logo");
+        assert_eq!(settings.images[0].path, "/tmp/logo.png");
+        assert_eq!(settings.texts[0].text.as_deref(), Some("Hello"));
+        assert_eq!(settings.scenes[0].name, "Screen");
+        assert_eq!(settings.scenes[0].ducking, Some(false));
+        assert!(settings.scenes[0].sources.contains_key("hdmi"));
+        assert_eq!(settings.scenes[0].sources["camera"].x, Some(0));
+        assert_eq!(settings.scenes[0].sources["camera"].width, Some(640));
+        assert_eq!(settings.broadcast.custom_url, "rtmp://localhost/live");
+        assert_eq!(settings.broadcast.stream_key, "key");
+        assert_eq!(settings.device.hdmi_device.as_deref(), Some("/dev/video0"));
+        assert_eq!(settings.media.hdmi_gain, Some(-3.0));
+        assert!(settings.media.ducking.enabled);
+        assert_eq!(settings.recording.replay, Some(30));
+        assert!(settings.meter.log_clips);
     }
 }
@@ -3,23 +3,110 @@ use gst::{element_error, glib};
 
 use anyhow::Error;
 
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
+use iced::futures::channel::oneshot;
 use iced::image;
 use single_value_channel::{channel_starting_with, Receiver, Updater};
 
+mod compositor;
+
+mod ducking;
+use ducking::Ducker;
+
 mod element;
-use element::{add_link, element, remove_many, MissingElement};
+use element::{add_link, downstream, element, remove_many, MissingElement};
+
+mod encoder;
+use encoder::VideoParams;
+
+mod layout;
+use layout::{Geometry, DEFAULT_TEXT_SIZE};
+
+mod loudness;
+pub use loudness::Loudness;
+use loudness::LoudnessMeter;
+
+mod meter;
+pub use meter::{ChannelLevel, Levels, FLOOR};
+use meter::{Clips, Meter};
+
+mod output;
+use output::Output;
+pub use output::OutputState;
+
+mod replay;
+use replay::Replay;
+
+mod snapshot;
+
+use crate::device::{CaptureDevice, DeviceKind};
+use crate::ingest::Protocol;
+use crate::setting::{
+    CaptureSetting, Compositor, H264Encoder, MicrophoneMode, NoiseSuppressionLevel,
+    RecordingContainer, Settings,
+};
+
+const SIGNAL_TIMEOUT: Duration = Duration::from_secs(2);
+const SIGNAL_RETRY: Duration = Duration::from_secs(3);
+const TEXT_POLL: Duration = Duration::from_millis(500);
+const MIN_GAIN: f64 = -60.0;
+const MAX_GAIN: f64 = 12.0;
+/// Gain changes are saved once no gain has been changed for this long.
+const GAIN_SAVE_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioSource {
+    Hdmi,
+    Mic,
+}
 
-use crate::setting::MicrophoneMode;
+impl AudioSource {
+    fn volume_name(&self) -> &'static str {
+        match self {
+            AudioSource::Hdmi => "hdmiaudio_volume",
+            AudioSource::Mic => "mic_volume",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            AudioSource::Hdmi => "HDMI audio",
+            AudioSource::Mic => "microphone",
+        }
+    }
+}
+
+struct Signal {
+    lost: Arc<AtomicBool>,
+    last_buffer: Arc<Mutex<Instant>>,
+}
 
 pub struct Stream {
     pipeline: gst::Pipeline,
+    compositor: Compositor,
     camera: bool,
     mic: bool,
     frame_ch: (Receiver<iced::image::Handle>, Updater<iced::image::Handle>),
-    sound_ch: (Receiver<(f32, f32)>, Updater<(f32, f32)>),
-    mic_ch: (Receiver<(f32, f32)>, Updater<(f32, f32)>),
+    last_frame: Arc<Mutex<Option<gst::Sample>>>,
+    sound_ch: (Receiver<Levels>, Updater<Levels>),
+    mic_ch: (Receiver<Levels>, Updater<Levels>),
+    hdmi_clips: Arc<Clips>,
+    mic_clips: Arc<Clips>,
+    loudness_ch: (Receiver<Loudness>, Updater<Loudness>),
+    loudness_reset: Arc<AtomicBool>,
+    rtmp: BTreeMap<usize, (Output, Receiver<OutputState>)>,
+    replay: Option<Replay>,
+    signal: Signal,
+    signal_retry: Instant,
+    scene: Arc<AtomicUsize>,
+    gain_changed: Option<Instant>,
+    /// Held while an output is torn down, so that none is tapped meanwhile
+    teardown: Arc<Mutex<()>>,
 }
 
 impl Default for Stream {
@@ -31,21 +118,42 @@ impl Default for Stream {
 impl Stream {
     pub fn new() -> Self {
         gst::init().unwrap();
+        if let Err(err) = crate::font::install() {
+            println!("Failed to install fonts for text overlays: {}", err);
+        }
 
         let pipeline = gst::Pipeline::new(None);
+        let compositor = Compositor::probe(crate::SETTINGS.read().unwrap().media.compositor);
         let frame_ch = channel_starting_with(image::Handle::from_pixels(1, 1, vec![0; 4]));
-        let sound_ch = channel_starting_with((0f32, 0f32));
-        let mic_ch = channel_starting_with((0f32, 0f32));
+        let sound_ch = channel_starting_with(Levels::default());
+        let mic_ch = channel_starting_with(Levels::default());
+        let loudness_ch = channel_starting_with(Loudness::default());
         let camera = false;
         let mic = false;
 
         Stream {
             pipeline,
+            compositor,
             frame_ch,
+            last_frame: Arc::new(Mutex::new(None)),
             sound_ch,
             mic_ch,
+            hdmi_clips: Arc::new(Clips::new(AudioSource::Hdmi)),
+            mic_clips: Arc::new(Clips::new(AudioSource::Mic)),
+            loudness_ch,
+            loudness_reset: Arc::new(AtomicBool::new(false)),
             camera,
             mic,
+            rtmp: BTreeMap::new(),
+            replay: None,
+            signal: Signal {
+                lost: Arc::new(AtomicBool::new(false)),
+                last_buffer: Arc::new(Mutex::new(Instant::now())),
+            },
+            signal_retry: Instant::now(),
+            scene: Arc::new(AtomicUsize::new(0)),
+            gain_changed: None,
+            teardown: Arc::new(Mutex::new(())),
         }
     }
 
@@ -53,14 +161,55 @@ impl Stream {
         self.frame_ch.0.latest()
     }
 
-    pub fn get_output_levels(&mut self) -> &(f32, f32) {
+    pub fn get_output_levels(&mut self) -> &Levels {
         self.sound_ch.0.latest()
     }
 
-    pub fn get_mic_levels(&mut self) -> &(f32, f32) {
+    pub fn get_mic_levels(&mut self) -> &Levels {
         self.mic_ch.0.latest()
     }
 
+    pub fn get_loudness(&mut self) -> &Loudness {
+        self.loudness_ch.0.latest()
+    }
+
+    /// Starts the integrated loudness over, as when going live.
+    pub fn reset_loudness(&self) {
+        self.loudness_reset.store(true, Ordering::SeqCst);
+    }
+
+    /// Number of clip events of the source and whether its indicator is latched.
+    pub fn clips(&self, source: AudioSource) -> (usize, bool) {
+        let clips = self.clips_of(source);
+        (clips.count(), clips.latched())
+    }
+
+    pub fn clear_clip(&self, source: AudioSource) {
+        self.clips_of(source).clear();
+    }
+
+    fn clips_of(&self, source: AudioSource) -> &Arc<Clips> {
+        match source {
+            AudioSource::Hdmi => &self.hdmi_clips,
+            AudioSource::Mic => &self.mic_clips,
+        }
+    }
+
+    /// Saves the latest program frame as PNG.
+    pub fn snapshot(&self, location: &str) {
+        match self.last_frame.lock().unwrap().clone() {
+            Some(sample) => snapshot::save(sample, location),
+            None => println!("No frame to take a snapshot of yet"),
+        }
+    }
+
+    pub fn get_rtmp_states(&mut self) -> Vec<(usize, OutputState)> {
+        self.rtmp
+            .iter_mut()
+            .map(|(id, (_, state_rx))| (*id, state_rx.latest().clone()))
+            .collect()
+    }
+
     pub fn camera_off(&self) -> bool {
         !self.camera
     }
@@ -69,37 +218,59 @@ impl Stream {
         !self.mic
     }
 
+    pub fn rtmp_off(&self) -> bool {
+        self.rtmp.is_empty()
+    }
+
+    pub fn destination_off(&self, id: usize) -> bool {
+        !self.rtmp.contains_key(&id)
+    }
+
+    pub fn recording_off(&self) -> bool {
+        self.pipeline.by_name("record_sink").is_none()
+    }
+
+    pub fn replay_off(&self) -> bool {
+        self.replay.is_none()
+    }
+
+    fn capture_caps(setting: &CaptureSetting) -> gst::Caps {
+        // Only constrain what is configured and let the source negotiate the rest
+        let mut structure = gst::Structure::new_empty("video/x-raw");
+        if let Some(width) = setting.width {
+            structure.set("width", width as i32);
+        }
+        if let Some(height) = setting.height {
+            structure.set("height", height as i32);
+        }
+        if let Some(framerate) = setting.framerate {
+            structure.set("framerate", gst::Fraction::new(framerate as i32, 1));
+        }
+        if let Some(format) = setting.format.as_ref() {
+            structure.set("format", format.as_str());
+        }
+
+        let mut caps = gst::Caps::new_empty();
+        caps.get_mut().unwrap().append_structure(structure);
+        caps
+    }
+
     pub fn create_videopipeline(&self) -> Result<(), Error> {
-        #[cfg(feature = "nativesrc")]
-        let src = element!("v4l2src")?;
-        #[cfg(feature = "testsrc")]
-        let src = element!("videotestsrc")?;
-        let srccapsfilter = element!("capsfilter")?;
-        let upload = element!("glupload")?;
-        let mixer = element!("glvideomixer", Some("videomix"))?;
+        let mixer = self.compositor.mixer(Some("videomix"))?;
         let tee = element!("tee", Some("videotee"))?;
         let queue0 = element!("queue")?;
-        let colorconvert = element!("glcolorconvert")?;
-        let download = element!("gldownload")?;
+        let download = self.compositor.download()?;
         let rate = element!("videorate")?;
         let sinkcapsfilter = element!("capsfilter")?;
         let queue = element!("queue")?;
         let sink = element!("appsink")?;
 
-        if let Some(device) = crate::SETTINGS.read().unwrap().device.hdmi_device.clone() {
-            src.set_property("device", device);
-        }
-
         add_link(
             &self.pipeline,
             &[
-                &src,
-                &srccapsfilter,
-                &upload,
                 &mixer,
                 &tee,
                 &queue0,
-                &colorconvert,
                 &download,
                 &rate,
                 &sinkcapsfilter,
@@ -112,14 +283,6 @@ impl Stream {
             .dynamic_cast::<gst_app::AppSink>()
             .expect("Sink element is expected to be an appsink!");
 
-        let caps = gst::Caps::builder("video/x-raw")
-            .field("width", 1280i32)
-            .field("height", 720i32)
-            .field("framerate", gst::Fraction::new(30, 1))
-            .field("format", gst_video::VideoFormat::Uyvy.to_str())
-            .build();
-        srccapsfilter.set_property("caps", &caps);
-
         let caps = gst::Caps::builder("video/x-raw")
             .field("framerate", gst::Fraction::new(5, 1))
             .field("format", gst_video::VideoFormat::Bgra.to_str())
@@ -127,6 +290,7 @@ impl Stream {
         sinkcapsfilter.set_property("caps", &caps);
 
         let frame_tx = self.frame_ch.1.clone();
+        let last_frame = self.last_frame.clone();
         appsink.set_callbacks(
             gst_app::AppSinkCallbacks::builder()
                 .new_sample(move |appsink| {
@@ -145,14 +309,178 @@ impl Stream {
                             gst::FlowError::Error
                         })?;
 
-                    let frame = image::Handle::from_pixels(1280, 720, buffer.to_vec());
+                    let info = sample
+                        .caps()
+                        .and_then(|caps| gst_video::VideoInfo::from_caps(caps).ok())
+                        .ok_or_else(|| {
+                            element_error!(
+                                appsink,
+                                gst::ResourceError::Failed,
+                                ("Failed to get video info from sample")
+                            );
+
+                            gst::FlowError::Error
+                        })?;
+
+                    let frame =
+                        image::Handle::from_pixels(info.width(), info.height(), buffer.to_vec());
                     frame_tx.update(frame).unwrap();
+                    // Kept as is for snapshots at full resolution
+                    *last_frame.lock().unwrap() = Some(sample.clone());
 
                     Ok(gst::FlowSuccess::Ok)
                 })
                 .build(),
         );
 
+        self.create_slate()?;
+        self.attach_hdmi()?;
+        self.create_images()?;
+        self.create_texts()?;
+
+        Ok(())
+    }
+
+    fn program_size(capture: &CaptureSetting) -> (i32, i32) {
+        (
+            capture.width.unwrap_or(1280) as i32,
+            capture.height.unwrap_or(720) as i32,
+        )
+    }
+
+    fn link_mixer_input(&self, upload: &gst::Element, zorder: u32) -> Result<gst::Pad, Error> {
+        let mix = self
+            .pipeline
+            .by_name("videomix")
+            .expect("mix element is not found in pipeline!");
+        let srcpad = upload.static_pad("src").unwrap();
+        let sinkpad = mix
+            .request_pad_simple("sink_%u")
+            .expect("If this happened, something is terribly wrong");
+        sinkpad.set_property("zorder", zorder);
+        srcpad.link(&sinkpad)?;
+        Ok(sinkpad)
+    }
+
+    fn remove_mixer_input(pipeline: &gst::Pipeline, prefix: &str) -> Result<(), Error> {
+        // Get existing elements
+        let elms: Vec<_> = ["src", "caps", "decode", "freeze", "convert", "upload"]
+            .iter()
+            .filter_map(|name| pipeline.by_name(&format!("{}_{}", prefix, name)))
+            .collect();
+        let mix = pipeline.by_name("videomix").unwrap();
+
+        let fakesink = element!("fakesink")?;
+        pipeline.add(&fakesink)?;
+
+        // Get srcpad of upload and sinkpad of mix
+        let srcpad = elms.last().and_then(|upload| upload.static_pad("src"));
+        let sinkpad = srcpad.as_ref().and_then(|pad| pad.peer());
+        if let (Some(srcpad), Some(sinkpad)) = (srcpad, sinkpad) {
+            // Change sink of upload from mix to fakesink
+            let fakepad = fakesink.static_pad("sink").unwrap();
+            srcpad.unlink(&sinkpad)?;
+            srcpad.link(&fakepad)?;
+
+            // Send EOS to mix in order to stop incoming stream
+            sinkpad.send_event(gst::event::Eos::new());
+
+            // Remove sink of the input
+            mix.release_request_pad(&sinkpad);
+        }
+
+        // Remove all unused elements
+        let mut elms: Vec<_> = elms.iter().collect();
+        elms.push(&fakesink);
+        remove_many(pipeline, &elms)?;
+
+        Ok(())
+    }
+
+    fn create_slate(&self) -> Result<(), Error> {
+        let src = element!("videotestsrc", Some("slate_src"))?;
+        let capsfilter = element!("capsfilter")?;
+        let overlay = element!("textoverlay")?;
+        let upload = self.compositor.upload(None)?;
+
+        src.set_property("is-live", true);
+        src.set_property_from_str("pattern", "black");
+        overlay.set_property("text", "No Signal");
+        overlay.set_property("font-desc", "Sans Bold 48");
+        overlay.set_property_from_str("valignment", "center");
+        overlay.set_property_from_str("halignment", "center");
+
+        let (width, height) = Self::program_size(&crate::SETTINGS.read().unwrap().capture);
+        let caps = gst::Caps::builder("video/x-raw")
+            .field("width", width)
+            .field("height", height)
+            .build();
+        capsfilter.set_property("caps", &caps);
+
+        add_link(&self.pipeline, &[&src, &capsfilter, &overlay, &upload])?;
+
+        // The slate sits below everything and shows through while HDMI is missing
+        self.link_mixer_input(&upload, 0)?;
+
+        Ok(())
+    }
+
+    fn attach_hdmi(&self) -> Result<(), Error> {
+        #[cfg(feature = "nativesrc")]
+        let src = element!("v4l2src", Some("hdmi_src"))?;
+        #[cfg(feature = "testsrc")]
+        let src = element!("videotestsrc", Some("hdmi_src"))?;
+        let capsfilter = element!("capsfilter", Some("hdmi_caps"))?;
+        let upload = self.compositor.upload(Some("hdmi_upload"))?;
+
+        if let Some(device) = crate::SETTINGS.read().unwrap().device.hdmi_device.clone() {
+            src.set_property("device", CaptureDevice::resolve(&device, DeviceKind::Video));
+        }
+
+        let caps = Self::capture_caps(&crate::SETTINGS.read().unwrap().capture);
+        capsfilter.set_property("caps", &caps);
+
+        add_link(&self.pipeline, &[&src, &capsfilter, &upload])?;
+
+        // Track incoming buffers to notice a source that silently stops
+        *self.signal.last_buffer.lock().unwrap() = Instant::now();
+        self.signal.lost.store(false, Ordering::SeqCst);
+        let last_buffer = self.signal.last_buffer.clone();
+        capsfilter
+            .static_pad("src")
+            .unwrap()
+            .add_probe(gst::PadProbeType::BUFFER, move |_, _| {
+                *last_buffer.lock().unwrap() = Instant::now();
+                gst::PadProbeReturn::Ok
+            });
+
+        self.link_mixer_input(&upload, 1)?;
+        self.update_layout();
+
+        Ok(())
+    }
+
+    /// Rebuilds the HDMI branch when the source stops or errors out,
+    /// and keeps retrying until a signal comes back.
+    pub fn watch_signal(&mut self) -> Result<(), Error> {
+        if self.pipeline.by_name("hdmi_src").is_some() {
+            let stalled = self.signal.last_buffer.lock().unwrap().elapsed() > SIGNAL_TIMEOUT;
+            if self.signal.lost.load(Ordering::SeqCst) || stalled {
+                println!("HDMI signal lost");
+                Self::remove_mixer_input(&self.pipeline, "hdmi")?;
+                self.signal_retry = Instant::now() + SIGNAL_RETRY;
+            }
+        } else if Instant::now() >= self.signal_retry {
+            let attached = self.attach_hdmi().and_then(|_| {
+                self.pipeline.set_state(gst::State::Playing)?;
+                Ok(())
+            });
+            if let Err(err) = attached {
+                println!("HDMI source is not ready: {}", err);
+                Self::remove_mixer_input(&self.pipeline, "hdmi")?;
+                self.signal_retry = Instant::now() + SIGNAL_RETRY;
+            }
+        }
         Ok(())
     }
 
@@ -163,76 +491,204 @@ impl Stream {
             #[cfg(feature = "testsrc")]
             let src = element!("videotestsrc", Some("camera_src"))?;
             let capsfilter = element!("capsfilter", Some("camera_caps"))?;
-            let upload = element!("glupload", Some("camera_upload"))?;
+            let upload = self.compositor.upload(Some("camera_upload"))?;
 
             if let Some(device) = crate::SETTINGS.read().unwrap().device.camera_device.clone() {
-                src.set_property("device", device);
+                src.set_property("device", CaptureDevice::resolve(&device, DeviceKind::Video));
             }
 
-            let mix = self
-                .pipeline
-                .by_name("videomix")
-                .expect("mix element is not found in pipeline!");
-
+            let width = crate::SETTINGS.read().unwrap().camera.width.unwrap_or(360);
             let caps = gst::Caps::builder("video/x-raw")
-                .field("width", 360i32)
+                .field("width", width as i32)
                 .build();
             capsfilter.set_property("caps", &caps);
 
+            self.create_border()?;
             add_link(&self.pipeline, &[&src, &capsfilter, &upload])?;
+            let sinkpad = self.link_mixer_input(&upload, 3)?;
 
-            let srcpad = upload.static_pad("src").unwrap();
-            let sinkpad = mix
-                .request_pad_simple("sink_%u")
-                .expect("If this happened, something is terribly wrong");
-            srcpad.link(&sinkpad)?;
+            // The height is only known once the camera caps are negotiated
+            self.follow_caps(&sinkpad);
+            self.update_layout();
 
             self.pipeline.set_state(gst::State::Playing)?;
 
             self.camera = true;
         } else {
-            // Get existing elements
-            let src = self.pipeline.by_name("camera_src").unwrap();
-            let capsfilter = self.pipeline.by_name("camera_caps").unwrap();
-            let upload = self.pipeline.by_name("camera_upload").unwrap();
-            let mix = self.pipeline.by_name("videomix").unwrap();
-
-            // Get srcpad of upload and sinkpad of mix
-            let srcpad = upload.static_pad("src").unwrap();
-            let sinkpads = mix.sink_pads();
-            let sinkpad = sinkpads.last().unwrap();
+            Self::remove_mixer_input(&self.pipeline, "camera")?;
+            Self::remove_mixer_input(&self.pipeline, "border")?;
 
-            // Change sink of upload from mix to fakesink
-            let fakesink = element!("fakesink")?;
-            self.pipeline.add(&fakesink)?;
-            let fakepad = fakesink.static_pad("sink").unwrap();
-            srcpad.unlink(sinkpad)?;
-            srcpad.link(&fakepad)?;
+            self.pipeline.set_state(gst::State::Playing)?;
 
-            // Send EOS to mix in order to stop incoming stream
-            sinkpad.send_event(gst::event::Eos::new());
+            self.camera = false;
+        }
+        Ok(())
+    }
 
-            // Remove sink of camera input
-            mix.release_request_pad(sinkpad);
+    /// Solid color input placed right behind the camera to draw its border.
+    fn create_border(&self) -> Result<(), Error> {
+        let src = element!("videotestsrc", Some("border_src"))?;
+        let capsfilter = element!("capsfilter", Some("border_caps"))?;
+        let upload = self.compositor.upload(Some("border_upload"))?;
 
-            // Remove all unused elements
-            remove_many(&self.pipeline, &[&src, &capsfilter, &upload, &fakesink])?;
+        src.set_property("is-live", true);
+        src.set_property_from_str("pattern", "solid-color");
 
-            self.pipeline.set_state(gst::State::Playing)?;
+        let caps = gst::Caps::builder("video/x-raw")
+            .field("width", 16i32)
+            .field("height", 16i32)
+            .build();
+        capsfilter.set_property("caps", &caps);
 
-            self.camera = false;
+        add_link(&self.pipeline, &[&src, &capsfilter, &upload])?;
+        self.link_mixer_input(&upload, 2)?;
+
+        Ok(())
+    }
+
+    /// Lays the mixer input out again whenever its caps, and so its size, change.
+    fn follow_caps(&self, sinkpad: &gst::Pad) {
+        let pipeline = self.pipeline.downgrade();
+        let scene = self.scene.clone();
+        sinkpad.connect_notify(Some("caps"), move |_, _| {
+            if let Some(pipeline) = pipeline.upgrade() {
+                apply_scene(&pipeline, scene.load(Ordering::SeqCst));
+            }
+        });
+    }
+
+    fn create_images(&self) -> Result<(), Error> {
+        let images = crate::SETTINGS.read().unwrap().images.clone();
+        for (index, image) in images.iter().enumerate() {
+            if !std::path::Path::new(&image.path).is_file() {
+                println!("Image {} is not found: {}", image.name, image.path);
+                continue;
+            }
+
+            let prefix = format!("image{}", index);
+            let src = element!("filesrc", Some(format!("{}_src", prefix).as_str()))?;
+            let decode = element!("pngdec", Some(format!("{}_decode", prefix).as_str()))?;
+            let freeze = element!("imagefreeze", Some(format!("{}_freeze", prefix).as_str()))?;
+            let convert = element!("videoconvert", Some(format!("{}_convert", prefix).as_str()))?;
+            let upload = self
+                .compositor
+                .upload(Some(format!("{}_upload", prefix).as_str()))?;
+
+            src.set_property("location", &image.path);
+            freeze.set_property("is-live", true);
+
+            add_link(&self.pipeline, &[&src, &decode, &freeze, &convert, &upload])?;
+
+            // Images stay above the camera, in the order they are configured
+            let sinkpad = self.link_mixer_input(&upload, 4 + index as u32)?;
+            // Keep it hidden until the decoded size is known
+            sinkpad.set_property("alpha", 0.0);
+            self.follow_caps(&sinkpad);
+        }
+
+        Ok(())
+    }
+
+    fn create_texts(&self) -> Result<(), Error> {
+        let (images, texts) = {
+            let setting = crate::SETTINGS.read().unwrap();
+            (setting.images.len(), setting.texts.clone())
+        };
+        for (index, text) in texts.iter().enumerate() {
+            let prefix = format!("text{}", index);
+            let src = element!("videotestsrc", Some(format!("{}_src", prefix).as_str()))?;
+            let capsfilter = element!("capsfilter", Some(format!("{}_caps", prefix).as_str()))?;
+            let overlay = element!("textoverlay", Some(format!("{}_overlay", prefix).as_str()))?;
+            let upload = self
+                .compositor
+                .upload(Some(format!("{}_upload", prefix).as_str()))?;
+
+            src.set_property("is-live", true);
+            src.set_property_from_str("pattern", "solid-color");
+            src.set_property("foreground-color", text.background.unwrap_or(0));
+
+            let program = Self::program_size(&crate::SETTINGS.read().unwrap().capture);
+            let geometry = Geometry::text(text, program);
+            let caps = gst::Caps::builder("video/x-raw")
+                .field("format", gst_video::VideoFormat::Bgra.to_str())
+                .field("width", geometry.width)
+                .field("height", geometry.height)
+                .build();
+            capsfilter.set_property("caps", &caps);
+
+            let font = text.font.as_deref().unwrap_or("IBM Plex Sans Bold");
+            let size = text.size.unwrap_or(DEFAULT_TEXT_SIZE);
+            overlay.set_property("font-desc", format!("{} {}", font, size));
+            overlay.set_property("color", text.color.unwrap_or(0xffff_ffff));
+            overlay.set_property("text", text.text.as_deref().unwrap_or(""));
+            overlay.set_property("shaded-background", false);
+            overlay.set_property("wait-text", false);
+            overlay.set_property_from_str("valignment", "center");
+            overlay.set_property_from_str("halignment", "left");
+
+            add_link(&self.pipeline, &[&src, &capsfilter, &overlay, &upload])?;
+
+            // Texts stay above images
+            self.link_mixer_input(&upload, 4 + (images + index) as u32)?;
+
+            if let Some(file) = text.file.clone() {
+                watch_text(&overlay, file);
+            }
         }
+        self.update_layout();
+
         Ok(())
     }
 
+    /// Updates texts that are not backed by a file from the settings.
+    pub fn update_texts(&self) {
+        let texts = crate::SETTINGS.read().unwrap().texts.clone();
+        for (index, text) in texts.iter().enumerate() {
+            if text.file.is_some() {
+                continue;
+            }
+            if let Some(overlay) = self.pipeline.by_name(&format!("text{}_overlay", index)) {
+                overlay.set_property("text", text.text.as_deref().unwrap_or(""));
+            }
+        }
+    }
+
+    /// Applies the scene and camera placement from the settings to the running mixer.
+    pub fn update_layout(&self) {
+        apply_scene(&self.pipeline, self.scene.load(Ordering::SeqCst));
+    }
+
+    pub fn scene_name(&self) -> Option<String> {
+        let setting = crate::SETTINGS.read().unwrap();
+        let scene = self.scene.load(Ordering::SeqCst);
+        setting
+            .scenes
+            .get(scene)
+            .or_else(|| setting.scenes.first())
+            .map(|scene| scene.name.clone())
+    }
+
+    pub fn next_scene(&mut self) {
+        let count = crate::SETTINGS.read().unwrap().scenes.len();
+        if count == 0 {
+            return;
+        }
+        let scene = (self.scene.load(Ordering::SeqCst) + 1) % count;
+        self.scene.store(scene, Ordering::SeqCst);
+        self.update_layout();
+    }
+
     pub fn create_audiopipeline(&mut self) -> Result<(), Error> {
         #[cfg(feature = "nativesrc")]
-        let src = element!("alsasrc")?;
+        let src = element!("alsasrc", Some("hdmiaudio_src"))?;
         #[cfg(feature = "testsrc")]
-        let src = element!("audiotestsrc")?;
+        let src = element!("audiotestsrc", Some("hdmiaudio_src"))?;
         let queue0 = element!("queue")?;
         let convert = element!("audioconvert")?;
         let capsfilter = element!("capsfilter")?;
+        let volume = element!("volume", Some(AudioSource::Hdmi.volume_name()))?;
+        let duck = element!("volume", Some("hdmiaudio_duck"))?;
+        let hdmi_level = element!("level", Some("hdmiaudio_level"))?;
         let mix = element!("audiomixer", Some("audiomix"))?;
         let tee = element!("tee", Some("audiotee"))?;
         let queue = element!("queue")?;
@@ -241,8 +697,20 @@ impl Stream {
 
         level.set_property("post-messages", true);
         level.set_property("interval", 30_000_000u64);
+        hdmi_level.set_property("post-messages", true);
+        hdmi_level.set_property("interval", 30_000_000u64);
         sink.set_property("sync", true);
 
+        if let Some(device) = crate::SETTINGS
+            .read()
+            .unwrap()
+            .device
+            .hdmi_audio_device
+            .clone()
+        {
+            src.set_property("device", device);
+        }
+
         add_link(
             &self.pipeline,
             &[
@@ -250,6 +718,9 @@ impl Stream {
                 &queue0,
                 &convert,
                 &capsfilter,
+                &volume,
+                &duck,
+                &hdmi_level,
                 &mix,
                 &tee,
                 &queue,
@@ -264,10 +735,148 @@ impl Stream {
             .build();
 
         capsfilter.set_property("caps", &caps);
+        self.apply_gain(AudioSource::Hdmi);
+
+        // Comes with gst-plugins-rs, which is not always installed
+        if gst::ElementFactory::find("ebur128level").is_some() {
+            let queue = element!("queue")?;
+            let loudness = element!("ebur128level", Some("output_loudness"))?;
+            let sink = element!("fakesink")?;
+
+            loudness.set_property_from_str("mode", "momentary-loudness+shortterm-loudness");
+            loudness.set_property("post-messages", true);
+            loudness.set_property("interval", 100_000_000u64);
+            sink.set_property("sync", true);
+
+            self.pipeline.add_many(&[&queue, &loudness, &sink])?;
+            gst::Element::link_many(&[&tee, &queue, &loudness, &sink])?;
+        } else {
+            println!("ebur128level is not available, loudness is not measured");
+        }
 
         Ok(())
     }
 
+    /// Switches running audio sources over to the capture devices in the settings.
+    pub fn update_audio_devices(&self) {
+        let devices = {
+            let setting = crate::SETTINGS.read().unwrap();
+            [
+                ("hdmiaudio_src", setting.device.hdmi_audio_device.clone()),
+                ("mic_src", setting.device.mic_device.clone()),
+            ]
+        };
+        for (name, device) in devices {
+            let src = match self.pipeline.by_name(name) {
+                Some(src) => src,
+                None => continue,
+            };
+            // No device set means the default ALSA device
+            let device = device.unwrap_or_else(|| String::from("default"));
+            if src.find_property("device").is_none() || src.property::<String>("device") == device {
+                continue;
+            }
+
+            // The device can only be changed while the source is stopped
+            src.set_state(gst::State::Null).ok();
+            src.set_property("device", &device);
+            if let Err(err) = src.sync_state_with_parent() {
+                println!("Failed to switch {} to {}: {}", name, device, err);
+            }
+        }
+    }
+
+    /// Applies the A/V sync delays from the settings as pad offsets.
+    pub fn update_sync(&self) {
+        let (hdmi, mic, video) = {
+            let setting = crate::SETTINGS.read().unwrap();
+            (
+                setting.media.hdmi_delay.unwrap_or(0),
+                setting.media.mic_delay.unwrap_or(0),
+                setting.media.video_delay.unwrap_or(0),
+            )
+        };
+        // Pad offsets can only delay, so advancing a source delays all the others instead
+        let base = hdmi.min(mic).min(video).min(0);
+        for (name, delay) in [
+            (AudioSource::Hdmi.volume_name(), hdmi),
+            ("mic_queue", mic),
+            ("videomix", video),
+        ] {
+            if let Some(elm) = self.pipeline.by_name(name) {
+                let pad = elm.static_pad("src").unwrap();
+                pad.set_offset((delay - base) * 1_000_000);
+            }
+        }
+    }
+
+    /// Gain in dB and mute state of the source.
+    pub fn gain(&self, source: AudioSource) -> (f64, bool) {
+        let setting = crate::SETTINGS.read().unwrap();
+        let (gain, mute) = match source {
+            AudioSource::Hdmi => (setting.media.hdmi_gain, setting.media.hdmi_mute),
+            AudioSource::Mic => (setting.media.mic_gain, setting.media.mic_mute),
+        };
+        (gain.unwrap_or(0.0), mute.unwrap_or(false))
+    }
+
+    pub fn adjust_gain(&mut self, source: AudioSource, delta: f64) {
+        self.update_gain(source, |gain, mute| {
+            ((gain + delta).clamp(MIN_GAIN, MAX_GAIN), mute)
+        });
+    }
+
+    pub fn toggle_mute(&mut self, source: AudioSource) {
+        self.update_gain(source, |gain, mute| (gain, !mute));
+    }
+
+    fn update_gain<F>(&mut self, source: AudioSource, f: F)
+    where
+        F: FnOnce(f64, bool) -> (f64, bool),
+    {
+        let (gain, mute) = self.gain(source);
+        let (gain, mute) = f(gain, mute);
+        {
+            let mut setting = crate::SETTINGS.write().unwrap();
+            let media = &mut (*setting).media;
+            match source {
+                AudioSource::Hdmi => {
+                    media.hdmi_gain = Some(gain);
+                    media.hdmi_mute = Some(mute);
+                }
+                AudioSource::Mic => {
+                    media.mic_gain = Some(gain);
+                    media.mic_mute = Some(mute);
+                }
+            }
+        }
+        self.gain_changed = Some(Instant::now());
+        self.apply_gain(source);
+    }
+
+    /// Writes pending gain changes to the config file, once they have settled.
+    pub fn save_gain(&mut self) {
+        match self.gain_changed {
+            Some(changed) if changed.elapsed() >= GAIN_SAVE_DELAY => {}
+            _ => return,
+        }
+        self.gain_changed = None;
+
+        let hdmi = self.gain(AudioSource::Hdmi);
+        let mic = self.gain(AudioSource::Mic);
+        if let Err(err) = Settings::save_gain(hdmi, mic) {
+            println!("Save setting failed: {}", err);
+        }
+    }
+
+    fn apply_gain(&self, source: AudioSource) {
+        let (gain, mute) = self.gain(source);
+        if let Some(volume) = self.pipeline.by_name(source.volume_name()) {
+            volume.set_property("volume", 10f64.powf(gain / 20.0));
+            volume.set_property("mute", mute);
+        }
+    }
+
     pub fn toggle_mic(&mut self) -> Result<(), Error> {
         if self.mic == false {
             #[cfg(feature = "nativesrc")]
@@ -279,12 +888,18 @@ impl Stream {
             let resample = element!("audioresample", Some("mic_resample"))?;
             let chmix = element!("audiochannelmix", Some("mic_chmix"))?;
             let capsfilter = element!("capsfilter", Some("mic_caps"))?;
+            let processing = Self::create_mic_processing()?;
+            let volume = element!("volume", Some(AudioSource::Mic.volume_name()))?;
             let level = element!("level", Some("mic_level"))?;
             let queue = element!("queue", Some("mic_queue"))?;
 
             level.set_property("post-messages", true);
             level.set_property("interval", 30_000_000u64);
 
+            if let Some(device) = crate::SETTINGS.read().unwrap().device.mic_device.clone() {
+                src.set_property("device", device);
+            }
+
             if let Some(mode) = crate::SETTINGS.read().unwrap().media.mic_mode.clone() {
                 match mode {
                     MicrophoneMode::ForceStereo => {
@@ -306,19 +921,13 @@ impl Stream {
                 .build();
             capsfilter.set_property("caps", &caps);
 
-            add_link(
-                &self.pipeline,
-                &[
-                    &src,
-                    &queue0,
-                    &convert,
-                    &resample,
-                    &chmix,
-                    &capsfilter,
-                    &level,
-                    &queue,
-                ],
-            )?;
+            let mut elms = vec![&src, &queue0, &convert, &resample, &chmix, &capsfilter];
+            elms.extend(processing.iter());
+            elms.extend([&volume, &level, &queue]);
+            add_link(&self.pipeline, &elms)?;
+            self.update_mic_processing();
+            self.apply_gain(AudioSource::Mic);
+            self.update_sync();
 
             let srcpad = queue.static_pad("src").unwrap();
             let sinkpad = mix
@@ -326,20 +935,21 @@ impl Stream {
                 .expect("If this happened, something is terribly wrong");
             srcpad.link(&sinkpad)?;
 
-            self.pipeline.set_state(gst::State::Playing)?;
+            // A device that fails to open is switched to the default one by run_loop
+            if let Err(err) = self.pipeline.set_state(gst::State::Playing) {
+                println!("Failed to start microphone: {}", err);
+            }
 
             self.mic = true;
         } else {
-            // Get existing elements
+            // Get existing elements, from the source up to the mixer
             let src = self.pipeline.by_name("mic_src").unwrap();
-            let queue0 = self.pipeline.by_name("mic_queue0").unwrap();
-            let convert = self.pipeline.by_name("mic_convert").unwrap();
-            let resample = self.pipeline.by_name("mic_resample").unwrap();
-            let chmix = self.pipeline.by_name("mic_chmix").unwrap();
-            let capsfilter = self.pipeline.by_name("mic_caps").unwrap();
-            let level = self.pipeline.by_name("mic_level").unwrap();
             let queue = self.pipeline.by_name("mic_queue").unwrap();
             let mix = self.pipeline.by_name("audiomix").unwrap();
+            let elms: Vec<_> = downstream(&src)
+                .into_iter()
+                .take_while(|elm| elm != &mix)
+                .collect();
 
             // Get srcpad of queue and sinkpad of mix
             let srcpad = queue.static_pad("src").unwrap();
@@ -360,20 +970,9 @@ impl Stream {
             mix.release_request_pad(sinkpad);
 
             // Remove all unused elements
-            remove_many(
-                &self.pipeline,
-                &[
-                    &src,
-                    &queue0,
-                    &convert,
-                    &resample,
-                    &chmix,
-                    &capsfilter,
-                    &level,
-                    &queue,
-                    &fakesink,
-                ],
-            )?;
+            let mut elms: Vec<_> = elms.iter().collect();
+            elms.push(&fakesink);
+            remove_many(&self.pipeline, &elms)?;
 
             self.pipeline.set_state(gst::State::Playing)?;
 
@@ -382,67 +981,472 @@ impl Stream {
         Ok(())
     }
 
-    fn setup_videoencoder(&self, mux: &gst::Element) -> Result<(), Error> {
+    /// Noise suppression, gate and compressor stages of the microphone. They are
+    /// always in place so that `update_mic_processing` can switch them live.
+    fn create_mic_processing() -> Result<Vec<gst::Element>, Error> {
+        let mut elms = vec![];
+        if gst::ElementFactory::find("webrtcdsp").is_some() {
+            // webrtcdsp only takes interleaved S16
+            let denoise = element!("webrtcdsp", Some("mic_denoise"))?;
+            denoise.set_property("echo-cancel", false);
+            denoise.set_property("gain-control", false);
+            elms.push(element!("audioconvert")?);
+            elms.push(denoise);
+            elms.push(element!("audioconvert")?);
+        } else {
+            println!("webrtcdsp is not available, noise suppression is disabled");
+        }
+
+        let gate = element!("audiodynamic", Some("mic_gate"))?;
+        gate.set_property_from_str("mode", "expander");
+        gate.set_property_from_str("characteristics", "hard-knee");
+        let compressor = element!("audiodynamic", Some("mic_compressor"))?;
+        compressor.set_property_from_str("mode", "compressor");
+        compressor.set_property_from_str("characteristics", "soft-knee");
+        elms.push(gate);
+        elms.push(compressor);
+
+        Ok(elms)
+    }
+
+    /// Applies the microphone processing settings. Disabled dynamics stages
+    /// are left in place with a neutral ratio.
+    pub fn update_mic_processing(&self) {
+        let processing = crate::SETTINGS.read().unwrap().media.mic_processing.clone();
+        let amplitude = |db: f64| 10f64.powf(db / 20.0) as f32;
+
+        if let Some(denoise) = self.pipeline.by_name("mic_denoise") {
+            let level = match processing.noise_suppression_level.unwrap_or_default() {
+                NoiseSuppressionLevel::Low => "low",
+                NoiseSuppressionLevel::Moderate => "moderate",
+                NoiseSuppressionLevel::High => "high",
+                NoiseSuppressionLevel::VeryHigh => "very-high",
+            };
+            denoise.set_property("noise-suppression", processing.noise_suppression);
+            denoise.set_property_from_str("noise-suppression-level", level);
+        }
+        if let Some(gate) = self.pipeline.by_name("mic_gate") {
+            // The expander multiplies the level below the threshold by the ratio
+            let ratio = if processing.gate { 10f32 } else { 1f32 };
+            gate.set_property("threshold", amplitude(processing.gate_threshold()));
+            gate.set_property("ratio", ratio);
+        }
+        if let Some(compressor) = self.pipeline.by_name("mic_compressor") {
+            // The compressor multiplies the level above the threshold by the ratio,
+            // so 4:1 is 0.25
+            let ratio = if processing.compressor {
+                1.0 / processing.compressor_ratio() as f32
+            } else {
+                1f32
+            };
+            compressor.set_property("threshold", amplitude(processing.compressor_threshold()));
+            compressor.set_property("ratio", ratio);
+        }
+    }
+
+    fn setup_videoencoder(&self, sink: &gst::Element, prefix: &str) -> Result<(), Error> {
         let videosrc = self.pipeline.by_name("videotee").unwrap();
-        let queue = element!("queue")?;
-        let colorconvert = element!("glcolorconvert")?;
-        let download = element!("gldownload")?;
+        let queue = element!("queue", Some(format!("{}_videoqueue", prefix).as_str()))?;
+        let download = self.compositor.download()?;
+        let rate = element!("videorate")?;
         let videocapsfilter = element!("capsfilter")?;
-        let enc = element!("v4l2h264enc")?;
+        let (params, encoder) = {
+            let setting = crate::SETTINGS.read().unwrap();
+            (
+                VideoParams::from_setting(&setting.encoding),
+                H264Encoder::probe(setting.encoding.encoder),
+            )
+        };
+        let encoder = encoder.ok_or(MissingElement("h264 encoder"))?;
+        let enc = encoder.make(&params)?;
+        let enccapsfilter = element!("capsfilter")?;
         let parse = element!("h264parse")?;
 
         let caps = gst::Caps::builder("video/x-raw")
-            .field("framerate", gst::Fraction::new(30, 1))
+            .field("framerate", gst::Fraction::new(params.framerate as i32, 1))
             .field("format", gst_video::VideoFormat::I420.to_str())
             .build();
         videocapsfilter.set_property("caps", &caps);
+        enccapsfilter.set_property("caps", &params.caps());
 
         add_link(
             &self.pipeline,
             &[
                 &queue,
-                &colorconvert,
                 &download,
+                &rate,
                 &videocapsfilter,
                 &enc,
+                &enccapsfilter,
                 &parse,
             ],
         )?;
         videosrc.link(&queue)?;
-        parse.link(mux)?;
+        parse.link(sink)?;
         Ok(())
     }
 
-    fn setup_audioencoder(&self, mux: &gst::Element) -> Result<(), Error> {
+    fn setup_audioencoder(&self, sink: &gst::Element, prefix: &str) -> Result<(), Error> {
         let audiosrc = self.pipeline.by_name("audiotee").unwrap();
-        let queue = element!("queue")?;
+        let queue = element!("queue", Some(format!("{}_audioqueue", prefix).as_str()))?;
         let enc = element!("voaacenc")?;
+        let bitrate = crate::SETTINGS
+            .read()
+            .unwrap()
+            .encoding
+            .audio_bitrate
+            .unwrap_or(128);
+        enc.set_property("bitrate", (bitrate * 1000) as i32);
         let aacparse = element!("aacparse")?;
         self.pipeline.add_many(&[&queue, &enc, &aacparse])?;
-        gst::Element::link_many(&[&audiosrc, &queue, &enc, &aacparse, &mux])?;
+        gst::Element::link_many(&[&audiosrc, &queue, &enc, &aacparse, sink])?;
 
         Ok(())
     }
 
-    pub fn start_rtmp(&self, location: &str) -> Result<(), Error> {
-        if self.pipeline.by_name("mux").is_some() {
+    fn start_encoder(&self) -> Result<(), Error> {
+        if self.pipeline.by_name("encvideotee").is_some() {
             return Ok(());
         }
-        let mux = element!("flvmux", Some("mux"))?;
-        let queue = element!("queue")?;
-        let sink = element!("rtmpsink")?;
+        let videotee = element!("tee", Some("encvideotee"))?;
+        let audiotee = element!("tee", Some("encaudiotee"))?;
+
+        for tee in [&videotee, &audiotee] {
+            tee.set_property("allow-not-linked", true);
+        }
+
+        self.pipeline.add_many(&[&videotee, &audiotee])?;
+        self.setup_videoencoder(&videotee, "encoder")?;
+        self.setup_audioencoder(&audiotee, "encoder")?;
+
+        Ok(())
+    }
+
+    fn release_encoder(pipeline: &gst::Pipeline) -> Result<(), Error> {
+        match pipeline.by_name("encvideotee") {
+            Some(tee) if tee.src_pads().is_empty() => Self::stop_output(pipeline, "encoder"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Feeds the encoder into the given sinks, which are already in the
+    /// pipeline. On error the sinks and their branch are removed again.
+    fn tap_encoder(
+        &self,
+        prefix: &str,
+        videosink: &gst::Element,
+        audiosink: &gst::Element,
+    ) -> Result<(), Error> {
+        let _teardown = self.teardown.lock().unwrap();
+        let result = self.link_tap(prefix, videosink, audiosink);
+        if result.is_err() {
+            self.remove_tap(prefix, &[videosink, audiosink]);
+        }
+        result
+    }
+
+    fn link_tap(
+        &self,
+        prefix: &str,
+        videosink: &gst::Element,
+        audiosink: &gst::Element,
+    ) -> Result<(), Error> {
+        self.start_encoder()?;
+
+        let videotee = self.pipeline.by_name("encvideotee").unwrap();
+        let audiotee = self.pipeline.by_name("encaudiotee").unwrap();
+        let videoqueue = element!("queue", Some(format!("{}_videoqueue", prefix).as_str()))?;
+        let audioqueue = element!("queue", Some(format!("{}_audioqueue", prefix).as_str()))?;
+
+        self.pipeline.add_many(&[&videoqueue, &audioqueue])?;
+        gst::Element::link_many(&[&videotee, &videoqueue, videosink])?;
+        gst::Element::link_many(&[&audiotee, &audioqueue, audiosink])?;
+
+        // Drop frames until the next keyframe so that the output starts decodable
+        let sinkpad = videoqueue.static_pad("sink").unwrap();
+        sinkpad.add_probe(gst::PadProbeType::BUFFER, |_, info| match info.data {
+            Some(gst::PadProbeData::Buffer(ref buffer))
+                if buffer.flags().contains(gst::BufferFlags::DELTA_UNIT) =>
+            {
+                gst::PadProbeReturn::Drop
+            }
+            _ => gst::PadProbeReturn::Remove,
+        });
+
+        self.pipeline.set_state(gst::State::Playing)?;
+
+        sinkpad.push_event(
+            gst_video::UpstreamForceKeyUnitEvent::builder()
+                .all_headers(true)
+                .build(),
+        );
+
+        Ok(())
+    }
+
+    fn remove_tap(&self, prefix: &str, sinks: &[&gst::Element]) {
+        let mut elms: Vec<gst::Element> = vec![];
+        for name in ["videoqueue", "audioqueue"] {
+            let queue = match self.pipeline.by_name(&format!("{}_{}", prefix, name)) {
+                Some(queue) => queue,
+                None => continue,
+            };
+            let sinkpad = queue.static_pad("sink").unwrap();
+            if let Some(srcpad) = sinkpad.peer() {
+                srcpad.unlink(&sinkpad).ok();
+                srcpad
+                    .parent_element()
+                    .unwrap()
+                    .release_request_pad(&srcpad);
+            }
+            elms.push(queue);
+        }
+        for sink in sinks {
+            elms.push((*sink).clone());
+        }
+
+        let mut branch: Vec<gst::Element> = vec![];
+        for elm in elms.iter().flat_map(downstream) {
+            if !branch.contains(&elm) {
+                branch.push(elm);
+            }
+        }
+        if let Err(err) = remove_many(&self.pipeline, &branch.iter().collect::<Vec<_>>()) {
+            println!("Failed to remove {} output: {}", prefix, err);
+        }
+        if let Err(err) = Self::release_encoder(&self.pipeline) {
+            println!("Failed to release encoder: {}", err);
+        }
+    }
+
+    pub fn start_rtmp(&mut self, id: usize, location: &str) -> Result<(), Error> {
+        if self.rtmp.contains_key(&id) {
+            return Ok(());
+        }
+        Protocol::from_url(location)?;
+
+        let prefix = format!("rtmp{}", id);
+        let videosink = element!("appsink", Some(format!("{}_videosink", prefix).as_str()))?;
+        let audiosink = element!("appsink", Some(format!("{}_audiosink", prefix).as_str()))?;
+
+        videosink.set_property("caps", &output::video_caps());
+        audiosink.set_property("caps", &output::audio_caps());
+
+        for sink in [&videosink, &audiosink] {
+            sink.set_property("sync", false);
+        }
+
+        self.pipeline.add_many(&[&videosink, &audiosink])?;
+
+        let (state_rx, state_tx) = channel_starting_with(OutputState::Stopped);
+        let output = Output::start(
+            &self.pipeline,
+            location,
+            videosink.downcast_ref::<gst_app::AppSink>().unwrap(),
+            audiosink.downcast_ref::<gst_app::AppSink>().unwrap(),
+            state_tx,
+        );
+        if let Err(err) = self.tap_encoder(&prefix, &videosink, &audiosink) {
+            // Stopping waits for the connection attempt to give up
+            thread::spawn(move || output.stop());
+            return Err(err);
+        }
+
+        if self.rtmp.is_empty() {
+            self.reset_loudness();
+        }
+        self.rtmp.insert(id, (output, state_rx));
+
+        Ok(())
+    }
+
+    /// Keeps the encoder running into the replay buffer, when one is configured.
+    pub fn start_replay(&mut self) -> Result<(), Error> {
+        let duration = match crate::SETTINGS.read().unwrap().recording.replay {
+            Some(duration) if self.replay.is_none() => duration,
+            _ => return Ok(()),
+        };
+
+        let videosink = element!("appsink", Some("replay_videosink"))?;
+        let audiosink = element!("appsink", Some("replay_audiosink"))?;
+
+        videosink.set_property("caps", &output::video_caps());
+        audiosink.set_property("caps", &output::audio_caps());
+
+        for sink in [&videosink, &audiosink] {
+            sink.set_property("sync", false);
+        }
+
+        self.pipeline.add_many(&[&videosink, &audiosink])?;
+
+        let replay = Replay::start(
+            videosink.downcast_ref::<gst_app::AppSink>().unwrap(),
+            audiosink.downcast_ref::<gst_app::AppSink>().unwrap(),
+            gst::ClockTime::from_seconds(duration),
+        );
+        self.tap_encoder("replay", &videosink, &audiosink)?;
+        self.replay = Some(replay);
+
+        Ok(())
+    }
+
+    pub fn save_replay(&self, location: &str) {
+        if let Some(replay) = &self.replay {
+            replay.save(location);
+        }
+    }
+
+    pub fn start_recording(
+        &self,
+        location: &str,
+        container: RecordingContainer,
+    ) -> Result<(), Error> {
+        if !self.recording_off() {
+            return Ok(());
+        }
+        let mux = match container {
+            RecordingContainer::Matroska => element!("matroskamux", Some("record_mux"))?,
+            RecordingContainer::Mp4 => {
+                let mux = element!("mp4mux", Some("record_mux"))?;
+                // Keep moov updated in reserved space so the file survives a crash
+                mux.set_property("reserved-max-duration", 6 * 3600 * 1_000_000_000u64);
+                mux.set_property("reserved-moov-update-period", 1_000_000_000u64);
+                mux
+            }
+            RecordingContainer::FragmentedMp4 => {
+                let mux = element!("mp4mux", Some("record_mux"))?;
+                mux.set_property("fragment-duration", 1000u32);
+                mux
+            }
+        };
+        let queue = element!("queue", Some("record_queue"))?;
+        let sink = element!("filesink", Some("record_sink"))?;
 
         sink.set_property("location", location);
 
         add_link(&self.pipeline, &[&mux, &queue, &sink])?;
-        self.setup_videoencoder(&mux)?;
-        self.setup_audioencoder(&mux)?;
+        self.tap_encoder("record", &mux, &mux)?;
 
-        self.pipeline.set_state(gst::State::Playing)?;
+        Ok(())
+    }
+
+    fn detach(queue: &gst::Element) -> Result<(), Error> {
+        let sinkpad = queue.static_pad("sink").unwrap();
+        let srcpad = sinkpad.peer().unwrap();
+        let tee = srcpad.parent_element().unwrap();
+
+        // Unlink the branch from tee and let it drain by itself
+        srcpad.unlink(&sinkpad)?;
+        tee.release_request_pad(&srcpad);
+        sinkpad.send_event(gst::event::Eos::new());
+
+        Ok(())
+    }
+
+    fn eos_probe(sink: &gst::Element) -> mpsc::Receiver<()> {
+        let (eos_tx, eos_rx) = mpsc::channel();
+        let eos_tx = Mutex::new(eos_tx);
+        let sinkpad = sink.static_pad("sink").unwrap();
+        sinkpad.add_probe(
+            gst::PadProbeType::EVENT_DOWNSTREAM,
+            move |_, info| match info.data {
+                Some(gst::PadProbeData::Event(ref event))
+                    if event.type_() == gst::EventType::Eos =>
+                {
+                    eos_tx.lock().unwrap().send(()).ok();
+                    gst::PadProbeReturn::Remove
+                }
+                _ => gst::PadProbeReturn::Ok,
+            },
+        );
+        eos_rx
+    }
+
+    fn stop_output(pipeline: &gst::Pipeline, prefix: &str) -> Result<(), Error> {
+        let videoqueue = match pipeline.by_name(&format!("{}_videoqueue", prefix)) {
+            Some(queue) => queue,
+            None => return Ok(()),
+        };
+        let audioqueue = pipeline.by_name(&format!("{}_audioqueue", prefix)).unwrap();
+
+        // Collect the whole output branch before unlinking it from tees
+        let mut elms = downstream(&videoqueue);
+        for elm in downstream(&audioqueue) {
+            if !elms.contains(&elm) {
+                elms.push(elm);
+            }
+        }
+        let drained: Vec<_> = elms
+            .iter()
+            .filter(|elm| elm.src_pads().is_empty())
+            .map(Self::eos_probe)
+            .collect();
+
+        Self::detach(&videoqueue)?;
+        Self::detach(&audioqueue)?;
+
+        // Give the muxer a chance to finish the stream before closing the output
+        for eos_rx in drained {
+            if eos_rx.recv_timeout(Duration::from_secs(5)).is_err() {
+                println!("Timed out waiting for {} output to drain", prefix);
+            }
+        }
+
+        remove_many(pipeline, &elms.iter().collect::<Vec<_>>())?;
 
         Ok(())
     }
 
+    /// Runs the teardown of an output on its own thread, as draining it can
+    /// take seconds. The receiver resolves once it is done.
+    fn teardown<F>(&self, prefix: String, stop: F) -> oneshot::Receiver<()>
+    where
+        F: FnOnce(&gst::Pipeline) -> Result<(), Error> + Send + 'static,
+    {
+        Self::spawn_teardown(&self.pipeline, &self.teardown, prefix, stop)
+    }
+
+    fn spawn_teardown<F>(
+        pipeline: &gst::Pipeline,
+        teardown: &Arc<Mutex<()>>,
+        prefix: String,
+        stop: F,
+    ) -> oneshot::Receiver<()>
+    where
+        F: FnOnce(&gst::Pipeline) -> Result<(), Error> + Send + 'static,
+    {
+        let pipeline = pipeline.clone();
+        let teardown = teardown.clone();
+        let (done_tx, done_rx) = oneshot::channel();
+        thread::spawn(move || {
+            let _teardown = teardown.lock().unwrap();
+            let result = stop(&pipeline).and_then(|_| Self::release_encoder(&pipeline));
+            if let Err(err) = result {
+                println!("Failed to stop {} output: {}", prefix, err);
+            }
+            done_tx.send(()).ok();
+        });
+        done_rx
+    }
+
+    pub fn stop_rtmp(&mut self, id: usize) -> oneshot::Receiver<()> {
+        let output = self.rtmp.remove(&id).map(|(output, _)| output);
+        let prefix = format!("rtmp{}", id);
+        self.teardown(prefix.clone(), move |pipeline| {
+            if let Some(output) = output {
+                Self::stop_output(pipeline, &prefix)?;
+                output.stop();
+            }
+            Ok(())
+        })
+    }
+
+    pub fn stop_recording(&self) -> oneshot::Receiver<()> {
+        self.teardown(String::from("record"), |pipeline| {
+            Self::stop_output(pipeline, "record")
+        })
+    }
+
     pub fn run_loop(&self) -> Result<(), Error> {
         self.pipeline.set_state(gst::State::Playing)?;
 
@@ -454,63 +1458,126 @@ impl Stream {
         let pipeline = self.pipeline.downgrade();
         let sound_tx = self.sound_ch.1.clone();
         let mic_tx = self.mic_ch.1.clone();
+        let hdmi_clips = self.hdmi_clips.clone();
+        let mic_clips = self.mic_clips.clone();
+        let loudness_tx = self.loudness_ch.1.clone();
+        let loudness_reset = self.loudness_reset.clone();
+        let scene = self.scene.clone();
+        let signal_lost = self.signal.lost.clone();
+        let teardown = self.teardown.clone();
 
         thread::spawn(move || {
             let pipeline = pipeline.upgrade().unwrap();
-            let mut last_output_levels = vec![0f32; 2];
-            let mut last_mic_levels = vec![0f32; 2];
-
-            let get_rms = |rms: &glib::ValueArray, ch: usize, last: &Vec<f32>| {
-                let value: f64 = rms.nth(ch as u32).unwrap().get().unwrap();
-                let rms = 10f64.powf(value / 20f64) as f32;
-                rms.max(last[ch] * 0.95)
-            };
+            let mut output_meter = Meter::default();
+            let mut hdmi_meter = Meter::with_clips(hdmi_clips);
+            let mut mic_meter = Meter::with_clips(mic_clips);
+            let mut loudness_meter = LoudnessMeter::default();
+            let mut ducker = Ducker::default();
+            let mut duck_gain = 0.0;
 
             for msg in bus.iter_timed(gst::ClockTime::NONE) {
                 use gst::MessageView;
 
                 match msg.view() {
                     MessageView::Element(_) => {
-                        match msg.structure() {
-                            Some(e) => {
-                                let rms: glib::ValueArray = e.value("rms").unwrap().get().unwrap();
-                                if rms.len() < 2 {
-                                    return;
+                        let structure = match msg.structure() {
+                            Some(structure) if structure.name() == "ebur128-level" => {
+                                if loudness_reset.swap(false, Ordering::SeqCst) {
+                                    loudness_meter.reset();
                                 }
+                                loudness_tx
+                                    .update(loudness_meter.update(structure))
+                                    .unwrap();
+                                continue;
+                            }
+                            Some(structure) if structure.name() == "level" => structure,
+                            _ => continue,
+                        };
 
-                                match &msg
-                                    .src()
-                                    .map(|s| s.name())
-                                    .as_ref()
-                                    .map(glib::GString::as_str)
-                                {
-                                    Some("output_level") => {
-                                        let levels = (
-                                            get_rms(&rms, 0, &last_output_levels),
-                                            get_rms(&rms, 1, &last_output_levels),
-                                        );
-                                        last_output_levels[0] = levels.0;
-                                        last_output_levels[1] = levels.1;
-
-                                        sound_tx.update(levels).unwrap()
-                                    }
-                                    Some("mic_level") => {
-                                        let levels = (
-                                            get_rms(&rms, 0, &last_mic_levels),
-                                            get_rms(&rms, 1, &last_mic_levels),
-                                        );
-                                        last_mic_levels[0] = levels.0;
-                                        last_mic_levels[1] = levels.1;
-
-                                        mic_tx.update(levels).unwrap()
-                                    }
-                                    _ => {}
+                        match &msg
+                            .src()
+                            .map(|s| s.name())
+                            .as_ref()
+                            .map(glib::GString::as_str)
+                        {
+                            Some("output_level") => {
+                                if let Some(levels) = output_meter.update(structure) {
+                                    sound_tx.update(levels).unwrap()
+                                }
+                            }
+                            Some("hdmiaudio_level") => {
+                                // Only watched for clipping, the output meter shows the mix
+                                hdmi_meter.update(structure);
+
+                                let gain = {
+                                    let setting = crate::SETTINGS.read().unwrap();
+                                    let enabled = setting
+                                        .scenes
+                                        .get(scene.load(Ordering::SeqCst))
+                                        .or_else(|| setting.scenes.first())
+                                        .and_then(|scene| scene.ducking)
+                                        .unwrap_or(setting.media.ducking.enabled);
+                                    ducker.step(&setting.media.ducking, enabled)
                                 };
+                                if gain != duck_gain {
+                                    duck_gain = gain;
+                                    if let Some(duck) = pipeline.by_name("hdmiaudio_duck") {
+                                        duck.set_property("volume", 10f64.powf(gain / 20.0));
+                                    }
+                                }
                             }
-                            None => (),
+                            Some("mic_level") => {
+                                if let Some(levels) = mic_meter.update(structure) {
+                                    ducker.mic(&levels);
+                                    mic_tx.update(levels).unwrap()
+                                }
+                            }
+                            _ => {}
                         };
                     }
                     MessageView::Eos(..) => break,
+                    MessageView::Error(err)
+                        if msg.src().map_or(false, |s| s.name().starts_with("hdmi_")) =>
+                    {
+                        // Recovered by Stream::watch_signal
+                        println!("HDMI source error: {}", err.error());
+                        signal_lost.store(true, Ordering::SeqCst);
+                    }
+                    MessageView::Error(err)
+                        if msg.src().map_or(false, |s| {
+                            matches!(s.name().as_str(), "hdmiaudio_src" | "mic_src")
+                        }) =>
+                    {
+                        // A missing or unplugged audio device must not take the app down
+                        let name = msg.src().unwrap().name();
+                        println!("Audio source {} error: {}", name, err.error());
+                        if let Some(src) = pipeline.by_name(&name) {
+                            fallback_to_default(&src);
+                        }
+                    }
+                    MessageView::Error(err)
+                        if msg.src().map_or(false, |s| s.name().starts_with("image")) =>
+                    {
+                        // A broken image file must not take the app down at startup
+                        let name = msg.src().unwrap().name();
+                        println!("Image {} error: {}", name, err.error());
+                        let prefix = name.split('_').next().unwrap();
+                        if let Err(err) = Self::remove_mixer_input(&pipeline, prefix) {
+                            println!("Failed to remove {}: {}", prefix, err);
+                        }
+                    }
+                    MessageView::Error(err)
+                        if msg.src().map_or(false, |s| s.name().starts_with("record_")) =>
+                    {
+                        // A full disk must not take the preview and live stream down
+                        println!("Recording error: {}", err.error());
+                        Self::spawn_teardown(
+                            &pipeline,
+                            &teardown,
+                            String::from("record"),
+                            |pipeline| Self::stop_output(pipeline, "record"),
+                        );
+                    }
                     MessageView::Error(err) => {
                         pipeline.set_state(gst::State::Null).unwrap();
                         panic!(
@@ -531,3 +1598,105 @@ impl Stream {
         Ok(())
     }
 }
+
+/// Restarts a failed audio source on the default ALSA device.
+fn fallback_to_default(src: &gst::Element) {
+    if src.find_property("device").is_none() || src.property::<String>("device") == "default" {
+        // Nothing left to fall back to, leave the source stopped
+        src.set_state(gst::State::Null).ok();
+        return;
+    }
+    println!("Falling back to the default device for {}", src.name());
+    src.set_state(gst::State::Null).ok();
+    src.set_property("device", "default");
+    if let Err(err) = src.sync_state_with_parent() {
+        println!("Failed to restart {}: {}", src.name(), err);
+    }
+}
+
+/// Polls the file and shows its content whenever it is modified.
+fn watch_text(overlay: &gst::Element, file: String) {
+    let overlay = overlay.downgrade();
+    thread::spawn(move || {
+        let mut modified = None;
+        while let Some(overlay) = overlay.upgrade() {
+            let current = std::fs::metadata(&file)
+                .and_then(|meta| meta.modified())
+                .ok();
+            if current.is_some() && current != modified {
+                modified = current;
+                match std::fs::read_to_string(&file) {
+                    Ok(text) => overlay.set_property("text", text.trim_end()),
+                    Err(err) => println!("Failed to read text from {}: {}", file, err),
+                }
+            }
+            drop(overlay);
+            thread::sleep(TEXT_POLL);
+        }
+    });
+}
+
+fn caps_size(pad: &gst::Pad) -> Option<(i32, i32)> {
+    let caps = pad.current_caps()?;
+    let info = gst_video::VideoInfo::from_caps(&caps).ok()?;
+    Some((info.width() as i32, info.height() as i32))
+}
+
+fn mixer_pad(pipeline: &gst::Pipeline, prefix: &str) -> Option<gst::Pad> {
+    pipeline
+        .by_name(&format!("{}_upload", prefix))
+        .and_then(|upload| upload.static_pad("src"))
+        .and_then(|pad| pad.peer())
+}
+
+/// Lays out every mixer input according to the active scene. Without any
+/// scenes configured all sources are shown at their default place.
+fn apply_scene(pipeline: &gst::Pipeline, scene: usize) {
+    let setting = crate::SETTINGS.read().unwrap();
+    let scene = setting.scenes.get(scene).or_else(|| setting.scenes.first());
+    let place = |name: &str, geometry: Geometry| match scene {
+        None => geometry,
+        Some(scene) => match scene.sources.get(name) {
+            Some(placement) => geometry.place(placement),
+            None => geometry.hide(),
+        },
+    };
+    // Taken from the guard above, a second read lock may deadlock behind a writer
+    let program = Stream::program_size(&setting.capture);
+
+    if let Some(pad) = mixer_pad(pipeline, "hdmi") {
+        place("hdmi", Geometry::full(program)).apply(&pad);
+    }
+
+    if let Some(pad) = mixer_pad(pipeline, "camera") {
+        // Assume 16:9 until the camera caps are negotiated
+        let aspect =
+            caps_size(&pad).map_or(16.0 / 9.0, |(width, height)| width as f64 / height as f64);
+        let geometry = place("camera", Geometry::pip(&setting.camera, program, aspect));
+        geometry.apply(&pad);
+
+        let border = setting.camera.border.unwrap_or(0) as i32;
+        if let Some(pad) = mixer_pad(pipeline, "border") {
+            geometry.frame(border).apply(&pad);
+        }
+        if let Some(src) = pipeline.by_name("border_src") {
+            let color = setting.camera.border_color.unwrap_or(0xffff_ffff);
+            src.set_property("foreground-color", color);
+        }
+    }
+
+    for (index, image) in setting.images.iter().enumerate() {
+        let pad = match mixer_pad(pipeline, &format!("image{}", index)) {
+            Some(pad) => pad,
+            None => continue,
+        };
+        if let Some(size) = caps_size(&pad) {
+            place(&image.name, Geometry::image(image, size)).apply(&pad);
+        }
+    }
+    for (index, text) in setting.texts.iter().enumerate() {
+        if let Some(pad) = mixer_pad(pipeline, &format!("text{}", index)) {
+            place(&text.name, Geometry::text(text, program)).apply(&pad);
+        }
+    }
+}
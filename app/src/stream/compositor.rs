@@ -0,0 +1,65 @@
+use gst::prelude::*;
+
+use anyhow::Error;
+
+use super::element::{element, MissingElement};
+use crate::setting::Compositor;
+
+impl Compositor {
+    /// Uses the preferred compositor if any, otherwise GL when a context can be created.
+    pub fn probe(preferred: Option<Compositor>) -> Compositor {
+        preferred.unwrap_or_else(|| {
+            if Self::gl_available() {
+                Compositor::Gl
+            } else {
+                println!("OpenGL is not available, compositing in software");
+                Compositor::Software
+            }
+        })
+    }
+
+    fn gl_available() -> bool {
+        if gst::ElementFactory::find("glvideomixer").is_none() {
+            return false;
+        }
+        let pipeline = match gst::parse_launch("gltestsrc num-buffers=1 ! fakesink") {
+            Ok(pipeline) => pipeline,
+            Err(_) => return false,
+        };
+        let available = pipeline.set_state(gst::State::Paused).is_ok()
+            && pipeline.state(gst::ClockTime::from_seconds(3)).0.is_ok();
+        pipeline.set_state(gst::State::Null).ok();
+        available
+    }
+
+    pub fn mixer(&self, name: Option<&str>) -> Result<gst::Element, Error> {
+        let mixer = match self {
+            Compositor::Gl => element!("glvideomixer", name)?,
+            Compositor::Software => {
+                let mixer = element!("compositor", name)?;
+                mixer.set_property_from_str("background", "black");
+                mixer
+            }
+        };
+        Ok(mixer)
+    }
+
+    /// Brings raw video into the memory the mixer works on.
+    pub fn upload(&self, name: Option<&str>) -> Result<gst::Element, Error> {
+        Ok(match self {
+            Compositor::Gl => element!("glupload", name)?,
+            Compositor::Software => element!("videoconvert", name)?,
+        })
+    }
+
+    /// Brings mixed video back into system memory for appsink and encoders.
+    pub fn download(&self) -> Result<gst::Element, Error> {
+        let description = match self {
+            Compositor::Gl => "glcolorconvert ! gldownload",
+            Compositor::Software => "videoconvert",
+        };
+        let bin = gst::parse_bin_from_description(description, true)
+            .map_err(|_| MissingElement(description))?;
+        Ok(bin.upcast())
+    }
+}
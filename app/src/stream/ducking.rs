@@ -0,0 +1,53 @@
+use std::time::{Duration, Instant};
+
+use super::Levels;
+use crate::setting::Ducking;
+
+/// Microphone levels older than this count as silence, as when the mic is off.
+const MIC_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Sidechain that lowers the HDMI audio while the microphone is loud.
+///
+/// It runs in software on the `level` messages: the microphone level is taken
+/// from `mic_level` and the gain is stepped on every `hdmiaudio_level` message.
+#[derive(Default)]
+pub struct Ducker {
+    mic: Option<(f32, Instant)>,
+    /// Current reduction in dB, zero or negative
+    gain: f64,
+    last_step: Option<Instant>,
+}
+
+impl Ducker {
+    pub fn mic(&mut self, levels: &Levels) {
+        self.mic = Some((levels.0.rms.max(levels.1.rms), Instant::now()));
+    }
+
+    /// Moves the gain towards its target and returns it in dB.
+    pub fn step(&mut self, setting: &Ducking, enabled: bool) -> f64 {
+        let now = Instant::now();
+        let elapsed = self.last_step.map_or(Duration::ZERO, |last| now - last);
+        self.last_step = Some(now);
+
+        let speaking = self.mic.map_or(false, |(rms, updated)| {
+            now - updated < MIC_TIMEOUT && rms as f64 > setting.threshold()
+        });
+        let amount = setting.amount();
+        let target = if enabled && speaking { -amount } else { 0.0 };
+
+        // Attack and release are the times for the full amount
+        let time = if target < self.gain {
+            setting.attack()
+        } else {
+            setting.release()
+        };
+        let step =
+            amount * elapsed.as_secs_f64() / time.max(Duration::from_millis(1)).as_secs_f64();
+        self.gain = if target < self.gain {
+            (self.gain - step).max(target)
+        } else {
+            (self.gain + step).min(target)
+        };
+        self.gain
+    }
+}
@@ -6,6 +6,10 @@ use gst::prelude::*;
 #[display(fmt = "Missing element {}", _0)]
 pub struct MissingElement(#[error(not(source))] pub &'static str);
 
+#[derive(Debug, Display, Error)]
+#[display(fmt = "Timed out waiting for end of stream")]
+pub struct EosTimeout;
+
 macro_rules! element {
     ($factoryname:expr) => {
         gst::ElementFactory::make($factoryname, None).map_err(|_| MissingElement($factoryname))
@@ -30,4 +34,32 @@ pub fn remove_many(pipeline: &gst::Pipeline, elms: &[&gst::Element]) -> Result<(
     Ok(())
 }
 
+/// Waits for a pipeline that was given all its data to finish writing it.
+pub fn wait_eos(pipeline: &gst::Pipeline, timeout: gst::ClockTime) -> Result<(), Error> {
+    let bus = pipeline
+        .bus()
+        .expect("Pipeline without bus. Shouldn't happen!");
+    let msg = bus
+        .timed_pop_filtered(timeout, &[gst::MessageType::Eos, gst::MessageType::Error])
+        .ok_or(EosTimeout)?;
+    match msg.view() {
+        gst::MessageView::Error(err) => Err(err.error().into()),
+        _ => Ok(()),
+    }
+}
+
+pub fn downstream(head: &gst::Element) -> Vec<gst::Element> {
+    let mut elms = vec![];
+    let mut next = Some(head.clone());
+    while let Some(elm) = next {
+        next = elm
+            .src_pads()
+            .first()
+            .and_then(|pad| pad.peer())
+            .and_then(|pad| pad.parent_element());
+        elms.push(elm);
+    }
+    elms
+}
+
 pub(crate) use element;
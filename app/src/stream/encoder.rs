@@ -0,0 +1,105 @@
+use gst::prelude::*;
+
+use anyhow::Error;
+
+use super::element::{element, MissingElement};
+use crate::setting::{EncodingSetting, H264Encoder, H264Profile};
+
+impl H264Profile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            H264Profile::Baseline => "baseline",
+            H264Profile::Main => "main",
+            H264Profile::High => "high",
+        }
+    }
+}
+
+/// Parameters every encoder is configured with, whichever one is in use.
+#[derive(Debug, Clone)]
+pub struct VideoParams {
+    /// Target bitrate in kbit/s
+    pub bitrate: u32,
+    /// Distance between keyframes in frames
+    pub keyframe_interval: u32,
+    pub profile: H264Profile,
+    pub level: Option<String>,
+    pub framerate: u32,
+}
+
+impl VideoParams {
+    pub fn from_setting(setting: &EncodingSetting) -> VideoParams {
+        let framerate = setting.framerate.unwrap_or(30).max(1);
+        VideoParams {
+            bitrate: setting.bitrate.unwrap_or(4000),
+            keyframe_interval: setting.keyframe_interval.unwrap_or(2).max(1) * framerate,
+            profile: setting.profile.unwrap_or(H264Profile::Main),
+            level: setting.level.clone(),
+            framerate,
+        }
+    }
+
+    /// Output caps carrying the profile and level, which all encoders negotiate from
+    pub fn caps(&self) -> gst::Caps {
+        let caps = gst::Caps::builder("video/x-h264").field("profile", self.profile.as_str());
+        match &self.level {
+            Some(level) => caps.field("level", level.as_str()).build(),
+            None => caps.build(),
+        }
+    }
+}
+
+impl H264Encoder {
+    const PREFERENCE: [H264Encoder; 3] =
+        [H264Encoder::V4l2, H264Encoder::X264, H264Encoder::OpenH264];
+
+    fn factory_name(&self) -> &'static str {
+        match self {
+            H264Encoder::V4l2 => "v4l2h264enc",
+            H264Encoder::X264 => "x264enc",
+            H264Encoder::OpenH264 => "openh264enc",
+        }
+    }
+
+    fn available(&self) -> bool {
+        gst::ElementFactory::find(self.factory_name()).is_some()
+    }
+
+    /// Picks the preferred encoder if installed, otherwise the first available one.
+    pub fn probe(preferred: Option<H264Encoder>) -> Option<H264Encoder> {
+        preferred
+            .into_iter()
+            .chain(Self::PREFERENCE)
+            .find(H264Encoder::available)
+    }
+
+    pub fn make(&self, params: &VideoParams) -> Result<gst::Element, Error> {
+        let enc = match self {
+            H264Encoder::V4l2 => {
+                let enc = element!("v4l2h264enc")?;
+                let controls = gst::Structure::builder("controls")
+                    .field("video_bitrate", (params.bitrate * 1000) as i32)
+                    .field("h264_i_frame_period", params.keyframe_interval as i32)
+                    .field("repeat_sequence_header", 1i32)
+                    .build();
+                enc.set_property("extra-controls", &controls);
+                enc
+            }
+            H264Encoder::X264 => {
+                let enc = element!("x264enc")?;
+                enc.set_property("bitrate", params.bitrate);
+                enc.set_property("key-int-max", params.keyframe_interval);
+                enc.set_property_from_str("tune", "zerolatency");
+                enc.set_property_from_str("speed-preset", "veryfast");
+                enc
+            }
+            H264Encoder::OpenH264 => {
+                let enc = element!("openh264enc")?;
+                enc.set_property("bitrate", params.bitrate * 1000);
+                enc.set_property("gop-size", params.keyframe_interval);
+                enc
+            }
+        };
+        Ok(enc)
+    }
+}
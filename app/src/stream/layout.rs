@@ -0,0 +1,119 @@
+use gst::prelude::*;
+
+use crate::setting::{CameraSetting, ImageSetting, PipPosition, Placement, TextSetting};
+
+const DEFAULT_WIDTH: u32 = 360;
+pub const DEFAULT_TEXT_SIZE: u32 = 32;
+
+/// Position, size and opacity of a mixer input, in program pixels.
+#[derive(Debug, Clone, Copy)]
+pub struct Geometry {
+    pub xpos: i32,
+    pub ypos: i32,
+    pub width: i32,
+    pub height: i32,
+    pub alpha: f64,
+}
+
+impl Geometry {
+    pub fn full(program: (i32, i32)) -> Geometry {
+        Geometry {
+            xpos: 0,
+            ypos: 0,
+            width: program.0,
+            height: program.1,
+            alpha: 1.0,
+        }
+    }
+
+    /// Camera picture-in-picture, keeping the aspect ratio of the camera feed.
+    pub fn pip(setting: &CameraSetting, program: (i32, i32), aspect: f64) -> Geometry {
+        let width = setting.width.unwrap_or(DEFAULT_WIDTH) as i32;
+        let height = (width as f64 / aspect).round() as i32;
+        let margin = (setting.margin.unwrap_or(0) + setting.border.unwrap_or(0)) as i32;
+
+        let right = program.0 - width - margin;
+        let bottom = program.1 - height - margin;
+        let (xpos, ypos) = match setting.position.unwrap_or_default() {
+            PipPosition::TopLeft => (margin, margin),
+            PipPosition::TopRight => (right, margin),
+            PipPosition::BottomLeft => (margin, bottom),
+            PipPosition::BottomRight => (right, bottom),
+            PipPosition::Custom => (setting.x.unwrap_or(0), setting.y.unwrap_or(0)),
+        };
+
+        Geometry {
+            xpos,
+            ypos,
+            width,
+            height,
+            alpha: setting.opacity.unwrap_or(1.0).clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn image(setting: &ImageSetting, size: (i32, i32)) -> Geometry {
+        let scale = setting.scale.unwrap_or(1.0);
+        Geometry {
+            xpos: setting.x.unwrap_or(0),
+            ypos: setting.y.unwrap_or(0),
+            width: (size.0 as f64 * scale).round() as i32,
+            height: (size.1 as f64 * scale).round() as i32,
+            alpha: setting.opacity.unwrap_or(1.0).clamp(0.0, 1.0),
+        }
+    }
+
+    /// Text box, spanning the program width and twice the font size by default.
+    pub fn text(setting: &TextSetting, program: (i32, i32)) -> Geometry {
+        let size = setting.size.unwrap_or(DEFAULT_TEXT_SIZE);
+        Geometry {
+            xpos: setting.x.unwrap_or(0),
+            ypos: setting.y.unwrap_or(0),
+            width: setting.width.map_or(program.0, |width| width as i32),
+            height: setting.height.unwrap_or(size * 2) as i32,
+            alpha: 1.0,
+        }
+    }
+
+    /// Overrides the geometry with a scene placement. When only one side is
+    /// given the other one follows the current aspect ratio.
+    pub fn place(self, placement: &Placement) -> Geometry {
+        let aspect = self.width as f64 / self.height as f64;
+        let (width, height) = match (placement.width, placement.height) {
+            (Some(width), Some(height)) => (width, height),
+            (Some(width), None) => (width, (width as f64 / aspect).round() as i32),
+            (None, Some(height)) => ((height as f64 * aspect).round() as i32, height),
+            (None, None) => (self.width, self.height),
+        };
+
+        Geometry {
+            xpos: placement.x.unwrap_or(self.xpos),
+            ypos: placement.y.unwrap_or(self.ypos),
+            width,
+            height,
+            alpha: placement.alpha.unwrap_or(self.alpha).clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn hide(self) -> Geometry {
+        Geometry { alpha: 0.0, ..self }
+    }
+
+    /// Surrounding frame of `border` pixels, invisible when there is no border.
+    pub fn frame(self, border: i32) -> Geometry {
+        Geometry {
+            xpos: self.xpos - border,
+            ypos: self.ypos - border,
+            width: self.width + border * 2,
+            height: self.height + border * 2,
+            alpha: if border > 0 { self.alpha } else { 0.0 },
+        }
+    }
+
+    pub fn apply(&self, pad: &gst::Pad) {
+        pad.set_property("xpos", self.xpos);
+        pad.set_property("ypos", self.ypos);
+        pad.set_property("width", self.width);
+        pad.set_property("height", self.height);
+        pad.set_property("alpha", self.alpha);
+    }
+}
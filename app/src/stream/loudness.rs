@@ -0,0 +1,72 @@
+/// Blocks quieter than this never count towards the integrated loudness.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks more than this below the ungated mean are left out as well.
+const RELATIVE_GATE: f64 = -10.0;
+
+/// EBU R128 loudness of the program mix in LUFS, `None` while silent.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Loudness {
+    pub momentary: Option<f64>,
+    pub short_term: Option<f64>,
+    pub integrated: Option<f64>,
+}
+
+/// Turns the messages of `ebur128level` into loudness values.
+///
+/// The integrated loudness is gated here rather than in the element so that it
+/// can be reset without touching the pipeline. The element posts the momentary
+/// loudness every 100 ms, which gives the 400 ms blocks with 75% overlap that
+/// the gating is defined on.
+#[derive(Default)]
+pub struct LoudnessMeter {
+    /// Mean square of every block above the absolute gate
+    blocks: Vec<f64>,
+}
+
+impl LoudnessMeter {
+    pub fn reset(&mut self) {
+        self.blocks.clear();
+    }
+
+    pub fn update(&mut self, structure: &gst::StructureRef) -> Loudness {
+        let value = |name: &str| -> Option<f64> {
+            structure
+                .get::<f64>(name)
+                .ok()
+                .filter(|lufs| lufs.is_finite())
+        };
+        let momentary = value("momentary-loudness");
+        let short_term = value("shortterm-loudness");
+
+        if let Some(lufs) = momentary.filter(|lufs| *lufs > ABSOLUTE_GATE) {
+            self.blocks.push(energy(lufs));
+        }
+
+        Loudness {
+            momentary,
+            short_term,
+            integrated: self.integrated(),
+        }
+    }
+
+    fn integrated(&self) -> Option<f64> {
+        if self.blocks.is_empty() {
+            return None;
+        }
+        let mean = self.blocks.iter().sum::<f64>() / self.blocks.len() as f64;
+        let gate = energy(loudness(mean) + RELATIVE_GATE);
+        let gated: Vec<f64> = self.blocks.iter().copied().filter(|e| *e > gate).collect();
+        if gated.is_empty() {
+            return None;
+        }
+        Some(loudness(gated.iter().sum::<f64>() / gated.len() as f64))
+    }
+}
+
+fn energy(lufs: f64) -> f64 {
+    10f64.powf((lufs + 0.691) / 10.0)
+}
+
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
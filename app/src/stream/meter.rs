@@ -0,0 +1,153 @@
+use gst::glib;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::AudioSource;
+
+/// Bottom of the meter scale in dBFS.
+pub const FLOOR: f32 = -60.0;
+const PEAK_HOLD: Duration = Duration::from_secs(2);
+/// Peaks at or above this level count as clipping.
+const CLIP_LEVEL: f32 = -0.1;
+
+/// Levels of one channel in dBFS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelLevel {
+    /// Sample peak, falling off smoothly as computed by the level element
+    pub peak: f32,
+    pub rms: f32,
+    /// Highest peak of the last couple of seconds
+    pub hold: f32,
+}
+
+impl ChannelLevel {
+    pub const SILENT: ChannelLevel = ChannelLevel {
+        peak: FLOOR,
+        rms: FLOOR,
+        hold: FLOOR,
+    };
+}
+
+impl Default for ChannelLevel {
+    fn default() -> ChannelLevel {
+        ChannelLevel::SILENT
+    }
+}
+
+pub type Levels = (ChannelLevel, ChannelLevel);
+
+/// Clip events of one source for the session. The indicator stays latched
+/// until it is cleared from the UI.
+pub struct Clips {
+    source: AudioSource,
+    count: AtomicUsize,
+    latched: AtomicBool,
+}
+
+impl Clips {
+    pub fn new(source: AudioSource) -> Self {
+        Clips {
+            source,
+            count: AtomicUsize::new(0),
+            latched: AtomicBool::new(false),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub fn latched(&self) -> bool {
+        self.latched.load(Ordering::SeqCst)
+    }
+
+    pub fn clear(&self) {
+        self.latched.store(false, Ordering::SeqCst);
+    }
+
+    fn record(&self) {
+        let count = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        self.latched.store(true, Ordering::SeqCst);
+
+        if crate::SETTINGS.read().unwrap().meter.log_clips {
+            let now = glib::DateTime::now_local()
+                .and_then(|now| now.format("%Y-%m-%d %H:%M:%S"))
+                .map(String::from)
+                .unwrap_or_default();
+            println!(
+                "Clipping on {} at {} (#{})",
+                self.source.label(),
+                now,
+                count
+            );
+        }
+    }
+}
+
+/// Turns the `level` messages of one source into stereo levels with peak hold.
+#[derive(Default)]
+pub struct Meter {
+    hold: [(f32, Option<Instant>); 2],
+    clips: Option<Arc<Clips>>,
+    clipping: bool,
+}
+
+impl Meter {
+    /// Meter that also counts clip events.
+    pub fn with_clips(clips: Arc<Clips>) -> Self {
+        Meter {
+            clips: Some(clips),
+            ..Meter::default()
+        }
+    }
+
+    pub fn update(&mut self, structure: &gst::StructureRef) -> Option<Levels> {
+        let values = |name: &str| -> Option<Vec<f32>> {
+            let array = structure.get::<glib::ValueArray>(name).ok()?;
+            let values: Vec<f32> = (0..array.len() as u32)
+                .filter_map(|ch| array.nth(ch)?.get::<f64>().ok())
+                .map(|db| (db as f32).max(FLOOR))
+                .collect();
+            if values.is_empty() {
+                None
+            } else {
+                Some(values)
+            }
+        };
+        let peak = values("peak")?;
+        let decay = values("decay")?;
+        let rms = values("rms")?;
+
+        // A clip that lasts several intervals is a single event
+        let clipping = peak.iter().any(|db| *db >= CLIP_LEVEL);
+        if let Some(clips) = &self.clips {
+            if clipping && !self.clipping {
+                clips.record();
+            }
+        }
+        self.clipping = clipping;
+
+        let now = Instant::now();
+        let mut channel = |ch: usize| -> ChannelLevel {
+            // A mono source shows on both channels
+            let index = ch
+                .min(peak.len() - 1)
+                .min(decay.len() - 1)
+                .min(rms.len() - 1);
+            let (hold, since) = &mut self.hold[ch];
+            let expired = since.map_or(true, |since| now.duration_since(since) > PEAK_HOLD);
+            if peak[index] >= *hold || expired {
+                *hold = peak[index];
+                *since = Some(now);
+            }
+            ChannelLevel {
+                peak: decay[index].max(peak[index]),
+                rms: rms[index],
+                hold: *hold,
+            }
+        };
+        Some((channel(0), channel(1)))
+    }
+}
@@ -0,0 +1,381 @@
+use gst::glib;
+use gst::prelude::*;
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use single_value_channel::Updater;
+
+use super::element::{add_link, element, MissingElement};
+use crate::ingest::Protocol;
+
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const STABLE_PERIOD: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputState {
+    Stopped,
+    Connecting(u32),
+    Live,
+    Waiting(u32),
+    Failed(String),
+}
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "TLS certificate rejected: {}", _0)]
+struct CertificateError(#[error(not(source))] String);
+
+/// `G_TLS_ERROR_BAD_CERTIFICATE` and `G_TLS_ERROR_CERTIFICATE_REQUIRED`,
+/// which retrying won't get past.
+const CERTIFICATE_ERRORS: [i32; 2] = [2, 5];
+
+impl Default for OutputState {
+    fn default() -> OutputState {
+        OutputState::Stopped
+    }
+}
+
+pub fn video_caps() -> gst::Caps {
+    gst::Caps::builder("video/x-h264")
+        .field("stream-format", "avc")
+        .field("alignment", "au")
+        .build()
+}
+
+pub fn audio_caps() -> gst::Caps {
+    gst::Caps::builder("audio/mpeg")
+        .field("mpegversion", 4i32)
+        .field("stream-format", "raw")
+        .build()
+}
+
+struct Sources {
+    video: gst_app::AppSrc,
+    audio: gst_app::AppSrc,
+}
+
+type SharedSources = Arc<Mutex<Option<Sources>>>;
+
+/// Network output running in its own pipeline, so that a failing connection
+/// never stalls the capture and preview in the main pipeline.
+pub struct Output {
+    sources: SharedSources,
+    stopping: Arc<AtomicBool>,
+    done: mpsc::Receiver<()>,
+}
+
+impl Output {
+    pub fn start(
+        main: &gst::Pipeline,
+        location: &str,
+        videosink: &gst_app::AppSink,
+        audiosink: &gst_app::AppSink,
+        state_tx: Updater<OutputState>,
+    ) -> Output {
+        let sources: SharedSources = Arc::new(Mutex::new(None));
+        let need_keyframe = Arc::new(AtomicBool::new(true));
+        let stopping = Arc::new(AtomicBool::new(false));
+        let (done_tx, done) = mpsc::channel();
+
+        let video_sources = sources.clone();
+        let video_eos = sources.clone();
+        let video_keyframe = need_keyframe.clone();
+        videosink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |appsink| {
+                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    if let Some(sources) = video_sources.lock().unwrap().as_ref() {
+                        let delta = sample.buffer().map_or(true, |buffer| {
+                            buffer.flags().contains(gst::BufferFlags::DELTA_UNIT)
+                        });
+                        // A fresh connection has to start from a keyframe
+                        if !(delta && video_keyframe.load(Ordering::SeqCst)) {
+                            video_keyframe.store(false, Ordering::SeqCst);
+                            sources.video.push_sample(&sample).ok();
+                        }
+                    }
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .eos(move |_| {
+                    if let Some(sources) = video_eos.lock().unwrap().as_ref() {
+                        sources.video.end_of_stream().ok();
+                    }
+                })
+                .build(),
+        );
+
+        let audio_sources = sources.clone();
+        let audio_eos = sources.clone();
+        audiosink.set_callbacks(
+            gst_app::AppSinkCallbacks::builder()
+                .new_sample(move |appsink| {
+                    let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                    if let Some(sources) = audio_sources.lock().unwrap().as_ref() {
+                        sources.audio.push_sample(&sample).ok();
+                    }
+                    Ok(gst::FlowSuccess::Ok)
+                })
+                .eos(move |_| {
+                    if let Some(sources) = audio_eos.lock().unwrap().as_ref() {
+                        sources.audio.end_of_stream().ok();
+                    }
+                })
+                .build(),
+        );
+
+        let supervisor = Supervisor {
+            main: main.downgrade(),
+            location: location.to_string(),
+            keyframe_pad: videosink.static_pad("sink").unwrap(),
+            sources: sources.clone(),
+            need_keyframe,
+            stopping: stopping.clone(),
+            state_tx,
+        };
+        thread::spawn(move || {
+            supervisor.run();
+            done_tx.send(()).ok();
+        });
+
+        Output {
+            sources,
+            stopping,
+            done,
+        }
+    }
+
+    pub fn stop(self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        if let Some(sources) = self.sources.lock().unwrap().as_ref() {
+            sources.video.end_of_stream().ok();
+            sources.audio.end_of_stream().ok();
+        }
+
+        if self
+            .done
+            .recv_timeout(DRAIN_TIMEOUT + Duration::from_secs(1))
+            .is_err()
+        {
+            println!("Timed out waiting for output to stop");
+        }
+    }
+}
+
+struct Supervisor {
+    main: glib::WeakRef<gst::Pipeline>,
+    location: String,
+    keyframe_pad: gst::Pad,
+    sources: SharedSources,
+    need_keyframe: Arc<AtomicBool>,
+    stopping: Arc<AtomicBool>,
+    state_tx: Updater<OutputState>,
+}
+
+impl Supervisor {
+    fn stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    fn run(&self) {
+        let mut attempt = 0;
+        loop {
+            self.state_tx.update(OutputState::Connecting(attempt)).ok();
+
+            let started = Instant::now();
+            match self.connect() {
+                Ok(()) => break,
+                Err(err) if err.is::<CertificateError>() => {
+                    // Retrying won't help until the certificate or URL is fixed
+                    println!("Output to {} failed: {}", self.location, err);
+                    self.state_tx
+                        .update(OutputState::Failed(String::from("TLS ERROR")))
+                        .ok();
+                    return;
+                }
+                Err(err) => println!("Output to {} failed: {}", self.location, err),
+            }
+            if self.stopping() {
+                break;
+            }
+
+            // Only keep backing off while the connection keeps failing quickly
+            if started.elapsed() > STABLE_PERIOD {
+                attempt = 0;
+            }
+            attempt += 1;
+            let backoff = Duration::from_secs(1 << attempt.min(6)).min(MAX_BACKOFF);
+            self.state_tx.update(OutputState::Waiting(attempt)).ok();
+
+            let until = Instant::now() + backoff;
+            while Instant::now() < until && !self.stopping() {
+                thread::sleep(Duration::from_millis(100));
+            }
+            if self.stopping() {
+                break;
+            }
+        }
+        self.state_tx.update(OutputState::Stopped).ok();
+    }
+
+    fn connect(&self) -> Result<(), Error> {
+        let pipeline = gst::Pipeline::new(None);
+        let videosrc = element!("appsrc")?;
+        let audiosrc = element!("appsrc")?;
+        let queue = element!("queue")?;
+
+        for src in [&videosrc, &audiosrc] {
+            src.set_property("format", gst::Format::Time);
+            src.set_property("is-live", true);
+        }
+        videosrc.set_property("caps", &video_caps());
+        audiosrc.set_property("caps", &audio_caps());
+        pipeline.add_many(&[&videosrc, &audiosrc])?;
+
+        let protocol = Protocol::from_url(&self.location)?;
+        let (mux, sink, video) = match protocol {
+            Protocol::Rtmp | Protocol::Rtmps => {
+                let mux = element!("flvmux")?;
+                // Only rtmp2sink speaks TLS
+                let sink = match protocol {
+                    Protocol::Rtmps => element!("rtmp2sink")?,
+                    _ => element!("rtmpsink")?,
+                };
+                mux.set_property("streamable", true);
+                sink.set_property("location", &self.location);
+                (mux, sink, videosrc.clone())
+            }
+            Protocol::Srt => {
+                // MPEG-TS carries H.264 as byte-stream with SPS/PPS in band
+                let mux = element!("mpegtsmux")?;
+                let parse = element!("h264parse")?;
+                let sink = element!("srtsink")?;
+                parse.set_property("config-interval", -1i32);
+                sink.set_property("uri", &self.location);
+                pipeline.add(&parse)?;
+                videosrc.link(&parse)?;
+                (mux, sink, parse)
+            }
+        };
+
+        add_link(&pipeline, &[&mux, &queue, &sink])?;
+        video.link(&mux)?;
+        audiosrc.link(&mux)?;
+
+        // Share clock and base time so buffer timestamps stay valid across pipelines
+        if let Some(main) = self.main.upgrade() {
+            pipeline.use_clock(main.clock().as_ref());
+            if let Some(base_time) = main.base_time() {
+                pipeline.set_base_time(base_time);
+            }
+            pipeline.set_start_time(gst::ClockTime::NONE);
+        }
+
+        *self.sources.lock().unwrap() = Some(Sources {
+            video: videosrc.dynamic_cast::<gst_app::AppSrc>().unwrap(),
+            audio: audiosrc.dynamic_cast::<gst_app::AppSrc>().unwrap(),
+        });
+        self.need_keyframe.store(true, Ordering::SeqCst);
+        self.keyframe_pad.push_event(
+            gst_video::UpstreamForceKeyUnitEvent::builder()
+                .all_headers(true)
+                .build(),
+        );
+
+        let result = self.watch(&pipeline);
+
+        *self.sources.lock().unwrap() = None;
+        pipeline.set_state(gst::State::Null)?;
+
+        result
+    }
+
+    fn watch(&self, pipeline: &gst::Pipeline) -> Result<(), Error> {
+        pipeline.set_state(gst::State::Playing)?;
+
+        let bus = pipeline
+            .bus()
+            .expect("Pipeline without bus. Shouldn't happen!");
+        let mut deadline = None;
+
+        loop {
+            if self.stopping() {
+                let deadline = *deadline.get_or_insert_with(|| Instant::now() + DRAIN_TIMEOUT);
+                if Instant::now() > deadline {
+                    return Ok(());
+                }
+            }
+
+            let msg = match bus.timed_pop(gst::ClockTime::from_mseconds(100)) {
+                Some(msg) => msg,
+                None => continue,
+            };
+
+            use gst::MessageView;
+
+            match msg.view() {
+                MessageView::StateChanged(..)
+                    if pipeline.current_state() == gst::State::Playing =>
+                {
+                    self.state_tx.update(OutputState::Live).ok();
+                }
+                MessageView::Eos(..) => return Ok(()),
+                MessageView::Error(err) => {
+                    let certificate = err
+                        .debug()
+                        .map_or(false, |debug| certificate_rejected(&debug));
+                    if certificate {
+                        return Err(CertificateError(err.error().to_string()).into());
+                    }
+                    return Err(err.error().into());
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Tells from the debug text of an error whether it came from a TLS
+/// certificate. `rtmp2sink` posts connection errors in the resource domain and
+/// only names the original domain and code in the debug text, as in
+/// `domain g-tls-error-quark, code 2`.
+fn certificate_rejected(debug: &str) -> bool {
+    let code = match debug.split_once("domain g-tls-error-quark, code ") {
+        Some((_, rest)) => rest,
+        None => return false,
+    };
+    let code: String = code
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == '-')
+        .collect();
+    code.parse()
+        .map_or(false, |code: i32| CERTIFICATE_ERRORS.contains(&code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certificate_rejected_from_debug() {
+        assert!(certificate_rejected(
+            "../gst/rtmp2/gstrtmp2sink.c(1077): send_connect_error (): \
+             /GstPipeline:pipeline0/GstRtmp2Sink:rtmp2sink0:\n\
+             domain g-tls-error-quark, code 2"
+        ));
+        assert!(certificate_rejected("domain g-tls-error-quark, code 5"));
+    }
+
+    #[test]
+    fn other_errors_are_retried() {
+        // Handshake failures and other domains may go away on the next attempt
+        assert!(!certificate_rejected("domain g-tls-error-quark, code 4"));
+        assert!(!certificate_rejected("domain g-io-error-quark, code 2"));
+        assert!(!certificate_rejected("Could not connect"));
+    }
+}
@@ -0,0 +1,164 @@
+use gst::prelude::*;
+
+use anyhow::Error;
+use derive_more::{Display, Error};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::element::{add_link, element, wait_eos, MissingElement};
+use super::output::{audio_caps, video_caps};
+
+const WRITE_TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(10);
+
+#[derive(Debug, Display, Error)]
+#[display(fmt = "No keyframe in the replay buffer")]
+struct NoKeyframe;
+
+#[derive(Default)]
+struct Samples {
+    video: VecDeque<gst::Sample>,
+    audio: VecDeque<gst::Sample>,
+}
+
+/// Rolling buffer of the last seconds of the encoded stream. It is fed from
+/// its own branch of the encoder tees, so saving never touches the outputs.
+pub struct Replay {
+    samples: Arc<Mutex<Samples>>,
+}
+
+impl Replay {
+    pub fn start(
+        videosink: &gst_app::AppSink,
+        audiosink: &gst_app::AppSink,
+        duration: gst::ClockTime,
+    ) -> Replay {
+        let samples = Arc::new(Mutex::new(Samples::default()));
+
+        for (sink, video) in [(videosink, true), (audiosink, false)] {
+            let samples = samples.clone();
+            sink.set_callbacks(
+                gst_app::AppSinkCallbacks::builder()
+                    .new_sample(move |appsink| {
+                        let sample = appsink.pull_sample().map_err(|_| gst::FlowError::Eos)?;
+                        let mut samples = samples.lock().unwrap();
+                        let queue = if video {
+                            &mut samples.video
+                        } else {
+                            &mut samples.audio
+                        };
+                        queue.push_back(sample);
+                        trim(queue, duration);
+                        Ok(gst::FlowSuccess::Ok)
+                    })
+                    .build(),
+            );
+        }
+
+        Replay { samples }
+    }
+
+    /// Writes what is buffered to an MP4 file in the background.
+    pub fn save(&self, location: &str) {
+        let (video, audio) = {
+            let samples = self.samples.lock().unwrap();
+            (
+                samples.video.iter().cloned().collect::<Vec<_>>(),
+                samples.audio.iter().cloned().collect::<Vec<_>>(),
+            )
+        };
+        let location = location.to_string();
+        thread::spawn(move || match write(&location, video, audio) {
+            Ok(()) => println!("Saved replay to {}", location),
+            Err(err) => println!("Failed to save replay to {}: {}", location, err),
+        });
+    }
+}
+
+fn timestamp(sample: &gst::Sample) -> Option<gst::ClockTime> {
+    sample.buffer()?.dts_or_pts()
+}
+
+fn keyframe(sample: &gst::Sample) -> bool {
+    sample.buffer().map_or(false, |buffer| {
+        !buffer.flags().contains(gst::BufferFlags::DELTA_UNIT)
+    })
+}
+
+fn trim(queue: &mut VecDeque<gst::Sample>, duration: gst::ClockTime) {
+    let latest = match queue.back().and_then(timestamp) {
+        Some(latest) => latest,
+        None => return,
+    };
+    while let Some(oldest) = queue.front().and_then(timestamp) {
+        if latest.saturating_sub(oldest) <= duration {
+            break;
+        }
+        queue.pop_front();
+    }
+}
+
+fn write(location: &str, video: Vec<gst::Sample>, audio: Vec<gst::Sample>) -> Result<(), Error> {
+    // The clip has to start from a keyframe to be decodable
+    let video: Vec<_> = video
+        .into_iter()
+        .skip_while(|sample| !keyframe(sample))
+        .collect();
+    let start = video.first().and_then(timestamp).ok_or(NoKeyframe)?;
+
+    let pipeline = gst::Pipeline::new(None);
+    let videosrc = element!("appsrc")?;
+    let audiosrc = element!("appsrc")?;
+    let mux = element!("mp4mux")?;
+    let sink = element!("filesink")?;
+
+    for src in [&videosrc, &audiosrc] {
+        src.set_property("format", gst::Format::Time);
+        // Everything is pushed at once
+        src.set_property("max-bytes", 0u64);
+    }
+    videosrc.set_property("caps", &video_caps());
+    audiosrc.set_property("caps", &audio_caps());
+    sink.set_property("location", location);
+
+    pipeline.add_many(&[&videosrc, &audiosrc])?;
+    add_link(&pipeline, &[&mux, &sink])?;
+    videosrc.link(&mux)?;
+    audiosrc.link(&mux)?;
+
+    pipeline.set_state(gst::State::Playing)?;
+
+    let result = push(&videosrc, video, start)
+        .and_then(|_| push(&audiosrc, audio, start))
+        .and_then(|_| wait_eos(&pipeline, WRITE_TIMEOUT));
+
+    pipeline.set_state(gst::State::Null)?;
+
+    result
+}
+
+/// Pushes the samples with their timestamps moved to start at zero.
+fn push(src: &gst::Element, samples: Vec<gst::Sample>, start: gst::ClockTime) -> Result<(), Error> {
+    let src = src.downcast_ref::<gst_app::AppSrc>().unwrap();
+    if let Some(caps) = samples.first().and_then(|sample| sample.caps_owned()) {
+        src.set_caps(Some(&caps));
+    }
+
+    for sample in samples {
+        let mut buffer = match sample.buffer_owned() {
+            Some(buffer) if buffer.dts_or_pts().map_or(false, |ts| ts >= start) => buffer,
+            _ => continue,
+        };
+        {
+            // Timestamps are running times of the main pipeline
+            let buffer = buffer.make_mut();
+            buffer.set_pts(buffer.pts().map(|pts| pts.saturating_sub(start)));
+            buffer.set_dts(buffer.dts().map(|dts| dts.saturating_sub(start)));
+        }
+        src.push_buffer(buffer)?;
+    }
+    src.end_of_stream()?;
+
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use gst::prelude::*;
+
+use anyhow::Error;
+
+use std::thread;
+
+use super::element::{add_link, element, wait_eos, MissingElement};
+
+const WRITE_TIMEOUT: gst::ClockTime = gst::ClockTime::from_seconds(5);
+
+/// Writes a frame of the preview branch to a PNG file in the background.
+pub fn save(sample: gst::Sample, location: &str) {
+    let location = location.to_string();
+    thread::spawn(move || match write(&sample, &location) {
+        Ok(()) => println!("Saved snapshot to {}", location),
+        Err(err) => println!("Failed to save snapshot to {}: {}", location, err),
+    });
+}
+
+fn write(sample: &gst::Sample, location: &str) -> Result<(), Error> {
+    let pipeline = gst::Pipeline::new(None);
+    let src = element!("appsrc")?;
+    let convert = element!("videoconvert")?;
+    let enc = element!("pngenc")?;
+    let sink = element!("filesink")?;
+
+    src.set_property("format", gst::Format::Time);
+    sink.set_property("location", location);
+
+    add_link(&pipeline, &[&src, &convert, &enc, &sink])?;
+    pipeline.set_state(gst::State::Playing)?;
+
+    let appsrc = src.downcast_ref::<gst_app::AppSrc>().unwrap();
+    let result = appsrc
+        .push_sample(sample)
+        .map_err(Error::from)
+        .and_then(|_| appsrc.end_of_stream().map_err(Error::from))
+        .and_then(|_| wait_eos(&pipeline, WRITE_TIMEOUT));
+
+    pipeline.set_state(gst::State::Null)?;
+
+    result
+}
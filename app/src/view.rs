@@ -1,6 +1,7 @@
 use iced::{Command, Element, Subscription};
 
 pub mod control;
+pub mod microphone;
 pub mod setting;
 
 pub trait ViewApp {
@@ -16,12 +17,14 @@ pub trait ViewApp {
 pub enum View {
     Control,
     Setting,
+    Microphone,
 }
 
 #[derive(Debug, Clone)]
 pub enum ViewMessage {
     Control(<control::App as ViewApp>::LocalMessage),
     Setting(<setting::App as ViewApp>::LocalMessage),
+    Microphone(<microphone::App as ViewApp>::LocalMessage),
 }
 
 impl Into<crate::Message> for ViewMessage {
@@ -41,3 +44,9 @@ impl Into<crate::Message> for <setting::App as ViewApp>::LocalMessage {
         crate::Message::ViewMessage(ViewMessage::Setting(self).into())
     }
 }
+
+impl Into<crate::Message> for <microphone::App as ViewApp>::LocalMessage {
+    fn into(self) -> crate::Message {
+        crate::Message::ViewMessage(ViewMessage::Microphone(self).into())
+    }
+}
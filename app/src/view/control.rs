@@ -1,17 +1,19 @@
+use iced::futures::channel::oneshot;
 use iced::{
-    alignment, button, time, Alignment, Color, Column, Command, Container, Element, Image, Length,
-    Row, Space, Subscription, Text,
+    alignment, button, time, Alignment, Button, Color, Column, Command, Container, Element, Image,
+    Length, Row, Space, Subscription, Text,
 };
 use iced_native::{keyboard, subscription, Event};
 use systemstat::{CPULoad, DelayedMeasurement, Platform, System};
 
 use anyhow::Error;
 
+use std::collections::BTreeMap;
 use std::time::{Duration, Instant};
 
 use crate::font;
 use crate::ingest::{IngestError, Service};
-use crate::stream;
+use crate::stream::{self, AudioSource};
 use crate::style;
 use crate::widget::{action, label, meter};
 use crate::View;
@@ -20,18 +22,39 @@ use crate::View;
 pub enum Message {
     Event(Event),
     UpdateFrame(Instant),
-    StartStream(Result<String, IngestError>),
+    StartStream(usize, Result<String, IngestError>),
     UpdateMetrics(Instant),
+    AdjustGain(AudioSource, f64),
+    UpdateSync,
+    UpdateMicProcessing,
+    ClearClip(AudioSource),
+    NextScene,
+    ToggleRecording,
+    OutputStopped,
 }
 
 #[derive(Default)]
 pub struct App {
     streamer: stream::Stream,
     rtmp_host: String,
+    destinations: usize,
+    /// Destinations that could not be started, with the reason
+    failures: BTreeMap<usize, String>,
+    /// Outputs that are still being torn down
+    stopping: usize,
     cpu: Option<DelayedMeasurement<CPULoad>>,
     cpu_usage: String,
     start: Option<Instant>,
+    record_start: Option<Instant>,
     settings: button::State,
+    mic_up: button::State,
+    mic_down: button::State,
+    hdmi_up: button::State,
+    hdmi_down: button::State,
+    hdmi_clip: button::State,
+    mic_clip: button::State,
+    scene: button::State,
+    record: button::State,
 }
 
 impl super::ViewApp for App {
@@ -42,6 +65,9 @@ impl super::ViewApp for App {
         streamer.create_videopipeline().unwrap();
         streamer.create_audiopipeline().unwrap();
         streamer.run_loop().unwrap();
+        if let Err(err) = streamer.start_replay() {
+            println!("Failed to start replay buffer: {}", err);
+        }
         let sys = System::new();
         let cpu = sys.cpu_load_aggregate().ok();
 
@@ -63,30 +89,137 @@ impl super::ViewApp for App {
     }
 
     fn view(&mut self) -> Element<crate::Message> {
-        let duration = match self.start {
-            Some(start) => start.elapsed(),
-            None => Duration::from_secs(0),
+        let stopwatch = |start: Option<Instant>| -> String {
+            let duration = match start {
+                Some(start) => start.elapsed(),
+                None => Duration::from_secs(0),
+            };
+            format!(
+                "{:02}:{:02}:{:02}",
+                duration.as_secs() / 3600,
+                (duration.as_secs() / 60) % 60,
+                duration.as_secs() % 60,
+            )
         };
-        let time = format!(
-            "{:02}:{:02}:{:02}",
-            duration.as_secs() / 3600,
-            (duration.as_secs() / 60) % 60,
-            duration.as_secs() % 60,
+        let time = stopwatch(self.start);
+        let mut rtmp_states = self.streamer.get_rtmp_states();
+        rtmp_states.extend(
+            self.failures
+                .iter()
+                .map(|(id, reason)| (*id, stream::OutputState::Failed(reason.clone()))),
         );
+        rtmp_states.sort_by_key(|(id, _)| *id);
+        let mut rtmp_state = rtmp_states
+            .iter()
+            .map(|(id, state)| {
+                let state = match state {
+                    stream::OutputState::Stopped => String::from("OFF"),
+                    stream::OutputState::Connecting(0) => String::from("CONNECTING"),
+                    stream::OutputState::Connecting(attempt) => format!("CONNECTING #{}", attempt),
+                    stream::OutputState::Live => String::from("LIVE"),
+                    stream::OutputState::Waiting(attempt) => format!("RETRY #{}", attempt),
+                    stream::OutputState::Failed(reason) => reason.clone(),
+                };
+                if self.destinations > 1 {
+                    format!("{}:{}", id + 1, state)
+                } else {
+                    state
+                }
+            })
+            .collect::<Vec<_>>();
+        if self.stopping > 0 {
+            rtmp_state.push(String::from("STOPPING"));
+        }
+        let scene = self.streamer.scene_name();
         let frame = (*self.streamer.get_frame()).clone();
         let image = Image::new(frame)
             .width(Length::Units(1024))
             .height(Length::Units(576));
 
+        let gain_text = |(gain, mute): (f64, bool)| -> Text {
+            Text::new(if mute {
+                String::from("MUTE")
+            } else {
+                format!("{:+.0}dB", gain)
+            })
+            .size(20)
+            .font(font::PLEXMONO)
+            .color(Color::WHITE)
+            .horizontal_alignment(alignment::Horizontal::Center)
+        };
+        let hdmi_gain = gain_text(self.streamer.gain(AudioSource::Hdmi));
+        let mic_gain = gain_text(self.streamer.gain(AudioSource::Mic));
+
+        let clip_text = |(count, latched): (usize, bool)| -> Text {
+            Text::new(format!("CLIP {}", count))
+                .size(20)
+                .font(font::PLEXMONO)
+                .color(if latched {
+                    Color::from_rgb8(255, 0, 0)
+                } else {
+                    Color::from_rgb8(96, 96, 96)
+                })
+                .horizontal_alignment(alignment::Horizontal::Center)
+        };
+        let hdmi_clip = Button::new(
+            &mut self.hdmi_clip,
+            clip_text(self.streamer.clips(AudioSource::Hdmi)),
+        )
+        .style(action::IconButton::Flat)
+        .padding(0)
+        .on_press(Message::ClearClip(AudioSource::Hdmi).into());
+        let mic_clip = Button::new(
+            &mut self.mic_clip,
+            clip_text(self.streamer.clips(AudioSource::Mic)),
+        )
+        .style(action::IconButton::Flat)
+        .padding(0)
+        .on_press(Message::ClearClip(AudioSource::Mic).into());
+
+        let (warning, clip) = {
+            let setting = crate::SETTINGS.read().unwrap();
+            (setting.meter.warning(), setting.meter.clip())
+        };
+        let level_meter = |level, height| {
+            meter::LevelMeter::new(level)
+                .height(Length::Units(height))
+                .thresholds(warning, clip)
+        };
+
+        let loudness = *self.streamer.get_loudness();
+        let loudness_text = |label: &str, lufs: Option<f64>| -> Text {
+            Text::new(match lufs {
+                Some(lufs) => format!("{} {:.1}", label, lufs),
+                None => format!("{} --", label),
+            })
+            .size(14)
+            .font(font::PLEXMONO)
+            .color(Color::WHITE)
+        };
+        let loudness = Column::new()
+            .align_items(Alignment::Start)
+            .push(loudness_text("M", loudness.momentary))
+            .push(loudness_text("S", loudness.short_term))
+            .push(loudness_text("I", loudness.integrated));
+
         let output_levels = self.streamer.get_output_levels();
 
         let meters: Element<_> = Container::new(
-            Row::new()
-                .width(Length::Fill)
-                .spacing(12)
-                .align_items(Alignment::Start)
-                .push(meter::LevelMeter::new(output_levels.0).height(Length::Units(576 - 12)))
-                .push(meter::LevelMeter::new(output_levels.1).height(Length::Units(576 - 12))),
+            Column::new()
+                .spacing(4)
+                .align_items(Alignment::Center)
+                .push(hdmi_clip)
+                .push(
+                    Row::new()
+                        .width(Length::Fill)
+                        .spacing(12)
+                        .align_items(Alignment::Start)
+                        .push(meter::MeterScale::new(Length::Units(576 - 110)))
+                        .push(level_meter(output_levels.0, 576 - 110))
+                        .push(level_meter(output_levels.1, 576 - 110)),
+                )
+                .push(loudness)
+                .push(hdmi_gain),
         )
         .padding(12)
         .center_x()
@@ -120,7 +253,7 @@ impl super::ViewApp for App {
             .size(36);
 
         let mic_levels = if self.streamer.mic_off() {
-            &(0.0, 0.0)
+            &(stream::ChannelLevel::SILENT, stream::ChannelLevel::SILENT)
         } else {
             self.streamer.get_mic_levels()
         };
@@ -131,13 +264,16 @@ impl super::ViewApp for App {
                 .align_items(Alignment::Center)
                 .push(Space::with_height(Length::Units(0)))
                 .push(mic_icon)
+                .push(mic_clip)
                 .push(
                     Row::new()
                         .spacing(12)
                         .align_items(Alignment::Start)
-                        .push(meter::LevelMeter::new(mic_levels.0).height(Length::Units(576 - 80)))
-                        .push(meter::LevelMeter::new(mic_levels.1).height(Length::Units(576 - 80))),
-                ),
+                        .push(meter::MeterScale::new(Length::Units(576 - 144)))
+                        .push(level_meter(mic_levels.0, 576 - 144))
+                        .push(level_meter(mic_levels.1, 576 - 144)),
+                )
+                .push(mic_gain),
         )
         .padding(12)
         .center_x()
@@ -150,31 +286,75 @@ impl super::ViewApp for App {
             .width(Length::Fill)
             .spacing(12)
             .align_items(Alignment::End)
-            .push(Space::with_height(Length::Units(104)))
-            .push(label::icon_text!(
-                font::Icon::Microphone,
-                "+",
-                label::Label::Inactive
-            ))
-            .push(label::icon_text!(
-                font::Icon::Microphone,
-                "-",
-                label::Label::Inactive
-            ))
-            .push(label::icon_text!(
-                font::Icon::VolumeOff,
-                "+",
-                label::Label::Inactive
-            ))
-            .push(label::icon_text!(
-                font::Icon::VolumeOff,
-                "-",
-                label::Label::Inactive
-            ))
-            .push(label::icon_text!(
-                font::Icon::Shuffle,
-                label::Label::Inactive
-            ))
+            .push(Space::with_height(Length::Units(12)))
+            .push(
+                Button::new(
+                    &mut self.mic_up,
+                    label::icon_text!(font::Icon::Microphone, "+", label::Label::Inactive),
+                )
+                .style(action::IconButton::Flat)
+                .padding(0)
+                .on_press(Message::AdjustGain(AudioSource::Mic, 1.0).into()),
+            )
+            .push(
+                Button::new(
+                    &mut self.mic_down,
+                    label::icon_text!(font::Icon::Microphone, "-", label::Label::Inactive),
+                )
+                .style(action::IconButton::Flat)
+                .padding(0)
+                .on_press(Message::AdjustGain(AudioSource::Mic, -1.0).into()),
+            )
+            .push(
+                Button::new(
+                    &mut self.hdmi_up,
+                    label::icon_text!(font::Icon::VolumeOff, "+", label::Label::Inactive),
+                )
+                .style(action::IconButton::Flat)
+                .padding(0)
+                .on_press(Message::AdjustGain(AudioSource::Hdmi, 1.0).into()),
+            )
+            .push(
+                Button::new(
+                    &mut self.hdmi_down,
+                    label::icon_text!(font::Icon::VolumeOff, "-", label::Label::Inactive),
+                )
+                .style(action::IconButton::Flat)
+                .padding(0)
+                .on_press(Message::AdjustGain(AudioSource::Hdmi, -1.0).into()),
+            )
+            .push(
+                Button::new(
+                    &mut self.scene,
+                    label::icon_text!(
+                        font::Icon::Shuffle,
+                        if scene.is_some() {
+                            label::Label::Active
+                        } else {
+                            label::Label::Inactive
+                        }
+                    ),
+                )
+                .style(action::IconButton::Flat)
+                .padding(0)
+                .on_press(Message::NextScene.into()),
+            )
+            .push(
+                Button::new(
+                    &mut self.record,
+                    label::icon_text!(
+                        font::Icon::CircleDot,
+                        if self.record_start.is_none() {
+                            label::Label::Inactive
+                        } else {
+                            label::Label::Active
+                        }
+                    ),
+                )
+                .style(action::IconButton::Flat)
+                .padding(0)
+                .on_press(Message::ToggleRecording.into()),
+            )
             .into();
 
         let side_actions: Element<_> = Row::new()
@@ -210,7 +390,7 @@ impl super::ViewApp for App {
                 .horizontal_alignment(alignment::Horizontal::Center)
         };
 
-        let status_area: Element<_> = Row::new()
+        let status_area = Row::new()
             .width(Length::Fill)
             .height(Length::Fill)
             .align_items(Alignment::Center)
@@ -221,7 +401,22 @@ impl super::ViewApp for App {
             .push(text(&time).width(Length::Units(150)))
             .push(icon(font::Icon::CloudArrowUp))
             .push(text(&self.rtmp_host).horizontal_alignment(alignment::Horizontal::Left))
-            .into();
+            .push(
+                Text::new(rtmp_state.join(" "))
+                    .size(24)
+                    .font(font::PLEXSANSBOLD)
+                    .color(Color::WHITE)
+                    .vertical_alignment(alignment::Vertical::Center),
+            );
+
+        let status_area: Element<_> = if self.record_start.is_none() {
+            status_area.into()
+        } else {
+            status_area
+                .push(icon(font::Icon::CircleDot).color(Color::from_rgb8(255, 0, 0)))
+                .push(text(&stopwatch(self.record_start)).width(Length::Units(150)))
+                .into()
+        };
 
         let bottom_actions: Element<_> = Row::new()
             .width(Length::Fill)
@@ -245,8 +440,15 @@ impl super::ViewApp for App {
                     label::Label::Active
                 },
             ))
-            .push(label::text("SCENE", label::Label::Inactive))
-            .push(label::text("START", label::Label::Primary))
+            .push(match &scene {
+                Some(name) => label::text(name, label::Label::Active),
+                None => label::text("SCENE", label::Label::Inactive),
+            })
+            .push(if self.streamer.rtmp_off() {
+                label::text("START", label::Label::Primary)
+            } else {
+                label::text("STOP", label::Label::Active)
+            })
             .into();
 
         let right_content: Element<_> = Column::new()
@@ -278,7 +480,11 @@ impl super::ViewApp for App {
 
     fn update(&mut self, message: Self::LocalMessage) -> Command<crate::Message> {
         match message {
-            Message::UpdateFrame(_) => {}
+            Message::UpdateFrame(_) => {
+                if let Err(err) = self.streamer.watch_signal() {
+                    println!("Failed to recover HDMI source: {}", err);
+                }
+            }
             Message::Event(event) => {
                 if let Event::Keyboard(keyboard::Event::KeyReleased { key_code, .. }) = event {
                     match key_code {
@@ -288,12 +494,66 @@ impl super::ViewApp for App {
                         keyboard::KeyCode::S => {
                             self.streamer.toggle_mic().unwrap();
                         }
+                        // Sits below the SCENE label on the reTerminal
+                        keyboard::KeyCode::D => {
+                            self.streamer.next_scene();
+                        }
+                        keyboard::KeyCode::C => {
+                            return self.toggle_recording();
+                        }
+                        keyboard::KeyCode::Q => {
+                            self.streamer.adjust_gain(AudioSource::Mic, 1.0);
+                        }
+                        keyboard::KeyCode::W => {
+                            self.streamer.adjust_gain(AudioSource::Mic, -1.0);
+                        }
+                        keyboard::KeyCode::E => {
+                            self.streamer.adjust_gain(AudioSource::Hdmi, 1.0);
+                        }
+                        keyboard::KeyCode::R => {
+                            self.streamer.adjust_gain(AudioSource::Hdmi, -1.0);
+                        }
+                        keyboard::KeyCode::Z => {
+                            self.streamer.toggle_mute(AudioSource::Mic);
+                        }
+                        keyboard::KeyCode::X => {
+                            self.streamer.toggle_mute(AudioSource::Hdmi);
+                        }
+                        keyboard::KeyCode::H => {
+                            if let Err(err) = self.save_replay() {
+                                println!("Failed to save replay: {}", err);
+                            }
+                        }
+                        keyboard::KeyCode::P => {
+                            if let Err(err) = self.snapshot() {
+                                println!("Failed to take snapshot: {}", err);
+                            }
+                        }
+                        keyboard::KeyCode::F if !self.streamer.rtmp_off() => {
+                            return self.stop_stream();
+                        }
                         keyboard::KeyCode::F => {
-                            return Command::perform(
-                                Service::get_ingest_url(),
-                                Message::StartStream,
-                            )
-                            .map(|e| e.into());
+                            return Command::batch(
+                                (0..self.destinations).map(|id| self.request_destination(id)),
+                            );
+                        }
+                        keyboard::KeyCode::Key1
+                        | keyboard::KeyCode::Key2
+                        | keyboard::KeyCode::Key3
+                        | keyboard::KeyCode::Key4
+                        | keyboard::KeyCode::Key5
+                        | keyboard::KeyCode::Key6
+                        | keyboard::KeyCode::Key7
+                        | keyboard::KeyCode::Key8
+                        | keyboard::KeyCode::Key9 => {
+                            let id = key_code as usize - keyboard::KeyCode::Key1 as usize;
+                            if id >= self.destinations {
+                                return Command::none();
+                            } else if self.streamer.destination_off(id) {
+                                return self.request_destination(id);
+                            } else {
+                                return self.stop_destination(id);
+                            }
                         }
                         _ => {
                             // TODO: Implement button actions [a/s/d/f]
@@ -302,19 +562,58 @@ impl super::ViewApp for App {
                     }
                 }
             }
-            Message::StartStream(url) => match url {
-                Ok(url) => self.start_stream(url).unwrap(),
-                _ => {}
-            },
-            Message::UpdateMetrics(_) => match self.cpu.as_ref() {
-                Some(cpu) => {
-                    let cpu = cpu.done().unwrap();
-                    self.cpu_usage = format!("{:3.0}%", cpu.user * 100.0);
+            Message::StartStream(id, url) => {
+                match url
+                    .map_err(Error::from)
+                    .and_then(|url| self.start_stream(id, url))
+                {
+                    Ok(()) => {
+                        self.failures.remove(&id);
+                    }
+                    Err(err) => {
+                        println!("Failed to start destination {}: {}", id + 1, err);
+                        self.failures.insert(id, err.to_string().to_uppercase());
+                    }
+                }
+            }
+            Message::AdjustGain(source, delta) => {
+                self.streamer.adjust_gain(source, delta);
+            }
+            Message::UpdateSync => {
+                self.streamer.update_sync();
+            }
+            Message::UpdateMicProcessing => {
+                self.streamer.update_mic_processing();
+            }
+            Message::ClearClip(source) => {
+                self.streamer.clear_clip(source);
+            }
+            Message::NextScene => {
+                self.streamer.next_scene();
+            }
+            Message::ToggleRecording => {
+                return self.toggle_recording();
+            }
+            Message::OutputStopped => {
+                self.stopping -= 1;
+            }
+            Message::UpdateMetrics(_) => {
+                match self.cpu.as_ref() {
+                    Some(cpu) => {
+                        let cpu = cpu.done().unwrap();
+                        self.cpu_usage = format!("{:3.0}%", cpu.user * 100.0);
+                    }
+                    None => {
+                        self.cpu_usage = String::from("n/a");
+                    }
                 }
-                None => {
-                    self.cpu_usage = String::from("n/a");
+                self.streamer.save_gain();
+                // A recording that fails to write tears itself down
+                if self.record_start.is_some() && self.streamer.recording_off() {
+                    println!("Recording stopped");
+                    self.record_start = None;
                 }
-            },
+            }
         }
 
         Command::none()
@@ -323,13 +622,18 @@ impl super::ViewApp for App {
 
 impl App {
     pub fn reload_setting(&mut self) {
+        self.streamer.update_layout();
+        self.streamer.update_texts();
+        self.streamer.update_sync();
+        self.streamer.update_audio_devices();
         let setting = crate::SETTINGS.read().unwrap();
-        let service = setting.broadcast.ingest_service;
-        self.rtmp_host = match service {
+        let destinations = setting.broadcast.destinations();
+        self.destinations = destinations.len();
+        self.rtmp_host = match destinations[0].ingest_service {
             Some(crate::ingest::Service::Custom) => {
-                let url: &str = &setting.broadcast.custom_url;
-                let v: Vec<_> = url.split('/').collect();
-                if v.len() > 3 {
+                let url: &str = &destinations[0].custom_url;
+                let v: Vec<_> = url.split(&['/', '?'][..]).collect();
+                if v.len() > 2 && !v[2].is_empty() {
                     v[2].to_string()
                 } else {
                     "Invalid host".to_string()
@@ -338,6 +642,9 @@ impl App {
             None => "Invalid host".to_string(),
             Some(service) => service.to_string(),
         };
+        if self.destinations > 1 && self.rtmp_host != "Invalid host".to_string() {
+            self.rtmp_host = format!("{} +{}", self.rtmp_host, self.destinations - 1);
+        }
         #[cfg(feature = "button-shim")]
         {
             let mut buttonshim = buttonshim::ButtonShim::new().unwrap();
@@ -349,10 +656,27 @@ impl App {
         }
     }
 
-    pub fn start_stream(&mut self, server_url: String) -> Result<(), Error> {
+    fn request_destination(&self, id: usize) -> Command<crate::Message> {
+        if self.stopping > 0 {
+            println!(
+                "Still stopping outputs, not starting destination {}",
+                id + 1
+            );
+            return Command::none();
+        }
+        Command::perform(Service::get_ingest_url(id), move |url| {
+            Message::StartStream(id, url)
+        })
+        .map(|e| e.into())
+    }
+
+    pub fn start_stream(&mut self, id: usize, server_url: String) -> Result<(), Error> {
         let stream_key = {
             let setting = crate::SETTINGS.read().unwrap();
-            setting.broadcast.stream_key.clone()
+            match setting.broadcast.destinations().get(id) {
+                Some(destination) => destination.stream_key.clone(),
+                None => return Ok(()),
+            }
         };
 
         let stream_url = &format!(
@@ -360,8 +684,10 @@ impl App {
             server_url.replace("{stream_key}", ""),
             stream_key = stream_key
         );
-        self.streamer.start_rtmp(stream_url)?;
-        self.start = Some(Instant::now());
+        self.streamer.start_rtmp(id, stream_url)?;
+        if self.start.is_none() {
+            self.start = Some(Instant::now());
+        }
 
         #[cfg(feature = "button-shim")]
         {
@@ -371,4 +697,108 @@ impl App {
 
         Ok(())
     }
+
+    /// Counts the output as stopping until its teardown is done.
+    fn stopping(&mut self, done: oneshot::Receiver<()>) -> Command<crate::Message> {
+        self.stopping += 1;
+        Command::perform(done, |_| Message::OutputStopped.into())
+    }
+
+    pub fn stop_destination(&mut self, id: usize) -> Command<crate::Message> {
+        let done = self.streamer.stop_rtmp(id);
+        if self.streamer.rtmp_off() {
+            self.start = None;
+
+            #[cfg(feature = "button-shim")]
+            {
+                let mut buttonshim = buttonshim::ButtonShim::new().unwrap();
+                buttonshim.led.set_pixel(0, 0xff, 0).unwrap();
+            }
+        }
+
+        self.stopping(done)
+    }
+
+    pub fn stop_stream(&mut self) -> Command<crate::Message> {
+        self.failures.clear();
+        let ids: Vec<_> = self
+            .streamer
+            .get_rtmp_states()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        Command::batch(ids.into_iter().map(|id| self.stop_destination(id)))
+    }
+
+    pub fn start_recording(&mut self) -> Result<(), Error> {
+        if self.stopping > 0 {
+            println!("Still stopping outputs, not starting recording");
+            return Ok(());
+        }
+        let (directory, container) = {
+            let setting = crate::SETTINGS.read().unwrap();
+            (
+                setting.recording.directory(),
+                setting.recording.container.unwrap_or_default(),
+            )
+        };
+        std::fs::create_dir_all(&directory)?;
+
+        let now = glib::DateTime::now_local()?.format("%Y%m%d-%H%M%S")?;
+        let location = directory.join(format!("{}.{}", now, container.extension()));
+        self.streamer
+            .start_recording(&location.to_string_lossy(), container)?;
+        self.record_start = Some(Instant::now());
+
+        Ok(())
+    }
+
+    pub fn save_replay(&self) -> Result<(), Error> {
+        if self.streamer.replay_off() {
+            println!("Replay buffer is off, set replay in the [recording] section");
+            return Ok(());
+        }
+        let directory = crate::SETTINGS.read().unwrap().recording.directory();
+        std::fs::create_dir_all(&directory)?;
+
+        let now = glib::DateTime::now_local()?.format("%Y%m%d-%H%M%S")?;
+        let location = directory.join(format!("{}-replay.mp4", now));
+        self.streamer.save_replay(&location.to_string_lossy());
+
+        Ok(())
+    }
+
+    pub fn snapshot(&self) -> Result<(), Error> {
+        let directory = crate::SETTINGS.read().unwrap().recording.directory();
+        std::fs::create_dir_all(&directory)?;
+
+        // Milliseconds keep snapshots taken in quick succession apart
+        let now = glib::DateTime::now_local()?;
+        let location = directory.join(format!(
+            "{}-{:03}-snapshot.png",
+            now.format("%Y%m%d-%H%M%S")?,
+            now.microsecond() / 1000
+        ));
+        self.streamer.snapshot(&location.to_string_lossy());
+
+        Ok(())
+    }
+
+    pub fn toggle_recording(&mut self) -> Command<crate::Message> {
+        if self.record_start.is_some() {
+            return self.stop_recording();
+        }
+        // record_start is only set once the recording has started
+        if let Err(err) = self.start_recording() {
+            println!("Failed to start recording: {}", err);
+        }
+        Command::none()
+    }
+
+    pub fn stop_recording(&mut self) -> Command<crate::Message> {
+        let done = self.streamer.stop_recording();
+        self.record_start = None;
+
+        self.stopping(done)
+    }
 }
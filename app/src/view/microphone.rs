@@ -0,0 +1,249 @@
+use iced::{
+    alignment, button, pick_list, scrollable, slider, Button, Checkbox, Column, Command, Container,
+    Element, Length, PickList, Scrollable, Slider, Subscription, Text,
+};
+
+use crate::setting::{Ducking, MicProcessing, NoiseSuppressionLevel};
+use crate::view::control;
+use crate::View;
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    ToggleNoiseSuppression(bool),
+    SelectNoiseSuppressionLevel(NoiseSuppressionLevel),
+    ToggleGate(bool),
+    GateThreshold(f64),
+    ToggleCompressor(bool),
+    CompressorThreshold(f64),
+    CompressorRatio(f64),
+    ToggleDucking(bool),
+    DuckingThreshold(f64),
+    DuckingAmount(f64),
+    UpdateSetting,
+}
+
+#[derive(Default)]
+pub struct App {
+    back: button::State,
+    scroll: scrollable::State,
+    select_level: pick_list::State<NoiseSuppressionLevel>,
+    gate_threshold: slider::State,
+    compressor_threshold: slider::State,
+    compressor_ratio: slider::State,
+    ducking_threshold: slider::State,
+    ducking_amount: slider::State,
+    processing: MicProcessing,
+    ducking: Ducking,
+}
+
+impl super::ViewApp for App {
+    type LocalMessage = Message;
+
+    fn new() -> Self {
+        let mut app = App::default();
+        app.refresh();
+        app
+    }
+
+    fn subscription(&self) -> Subscription<Self::LocalMessage> {
+        Subscription::none()
+    }
+
+    fn update(&mut self, message: Self::LocalMessage) -> Command<crate::Message> {
+        match message {
+            Message::ToggleNoiseSuppression(enabled) => {
+                self.processing.noise_suppression = enabled;
+            }
+            Message::SelectNoiseSuppressionLevel(level) => {
+                self.processing.noise_suppression_level = Some(level);
+            }
+            Message::ToggleGate(enabled) => {
+                self.processing.gate = enabled;
+            }
+            Message::GateThreshold(threshold) => {
+                self.processing.gate_threshold = Some(threshold);
+            }
+            Message::ToggleCompressor(enabled) => {
+                self.processing.compressor = enabled;
+            }
+            Message::CompressorThreshold(threshold) => {
+                self.processing.compressor_threshold = Some(threshold);
+            }
+            Message::CompressorRatio(ratio) => {
+                self.processing.compressor_ratio = Some(ratio);
+            }
+            Message::ToggleDucking(enabled) => {
+                self.ducking.enabled = enabled;
+            }
+            Message::DuckingThreshold(threshold) => {
+                self.ducking.threshold = Some(threshold);
+            }
+            Message::DuckingAmount(amount) => {
+                self.ducking.amount = Some(amount);
+            }
+            Message::UpdateSetting => {
+                let setting = crate::SETTINGS.read().unwrap();
+                if let Err(err) = setting.save() {
+                    println!("Save setting failed: {}", err);
+                }
+                return Command::perform(async { View::Setting }, crate::Message::ChangeView);
+            }
+        }
+
+        // Apply right away so that the processing can be tuned by ear
+        {
+            let mut setting = crate::SETTINGS.write().unwrap();
+            (*setting).media.mic_processing = self.processing.clone();
+            (*setting).media.ducking = self.ducking.clone();
+        }
+        Command::perform(async {}, |_| control::Message::UpdateMicProcessing.into())
+    }
+
+    fn view(&mut self) -> Element<crate::Message> {
+        let title = Text::new("Microphone")
+            .size(40)
+            .horizontal_alignment(alignment::Horizontal::Center)
+            .width(Length::Fill);
+
+        let label = |text: String| -> Text {
+            Text::new(text)
+                .size(20)
+                .horizontal_alignment(alignment::Horizontal::Left)
+                .width(Length::Fill)
+        };
+
+        let noise_suppression = Checkbox::new(
+            self.processing.noise_suppression,
+            "Noise Suppression",
+            |event| Message::ToggleNoiseSuppression(event).into(),
+        )
+        .width(Length::Fill);
+
+        let select_level = PickList::new(
+            &mut self.select_level,
+            &NoiseSuppressionLevel::ALL[..],
+            Some(self.processing.noise_suppression_level.unwrap_or_default()),
+            |event| Message::SelectNoiseSuppressionLevel(event).into(),
+        )
+        .padding(10)
+        .width(Length::Fill);
+
+        let gate = Checkbox::new(self.processing.gate, "Noise Gate", |event| {
+            Message::ToggleGate(event).into()
+        })
+        .width(Length::Fill);
+
+        let gate_threshold = Slider::new(
+            &mut self.gate_threshold,
+            -80.0..=0.0,
+            self.processing.gate_threshold(),
+            |event| Message::GateThreshold(event).into(),
+        )
+        .step(1.0);
+
+        let compressor = Checkbox::new(self.processing.compressor, "Compressor", |event| {
+            Message::ToggleCompressor(event).into()
+        })
+        .width(Length::Fill);
+
+        let compressor_threshold = Slider::new(
+            &mut self.compressor_threshold,
+            -60.0..=0.0,
+            self.processing.compressor_threshold(),
+            |event| Message::CompressorThreshold(event).into(),
+        )
+        .step(1.0);
+
+        let compressor_ratio = Slider::new(
+            &mut self.compressor_ratio,
+            1.0..=20.0,
+            self.processing.compressor_ratio(),
+            |event| Message::CompressorRatio(event).into(),
+        )
+        .step(0.5);
+
+        let ducking = Checkbox::new(self.ducking.enabled, "Duck HDMI Audio", |event| {
+            Message::ToggleDucking(event).into()
+        })
+        .width(Length::Fill);
+
+        let ducking_threshold = Slider::new(
+            &mut self.ducking_threshold,
+            -80.0..=0.0,
+            self.ducking.threshold(),
+            |event| Message::DuckingThreshold(event).into(),
+        )
+        .step(1.0);
+
+        let ducking_amount = Slider::new(
+            &mut self.ducking_amount,
+            0.0..=40.0,
+            self.ducking.amount(),
+            |event| Message::DuckingAmount(event).into(),
+        )
+        .step(1.0);
+
+        let save_button = Button::new(&mut self.back, Text::new("Save"))
+            .padding(10)
+            .on_press(Message::UpdateSetting.into());
+
+        let content: Element<_> = Column::new()
+            .spacing(20)
+            .padding(20)
+            .align_items(alignment::Alignment::Center)
+            .width(Length::Units(800))
+            .push(title)
+            .push(noise_suppression)
+            .push(select_level)
+            .push(gate)
+            .push(label(format!(
+                "Gate Threshold: {:.0} dB",
+                self.processing.gate_threshold()
+            )))
+            .push(gate_threshold)
+            .push(compressor)
+            .push(label(format!(
+                "Compressor Threshold: {:.0} dB",
+                self.processing.compressor_threshold()
+            )))
+            .push(compressor_threshold)
+            .push(label(format!(
+                "Compressor Ratio: {:.1}:1",
+                self.processing.compressor_ratio()
+            )))
+            .push(compressor_ratio)
+            .push(ducking)
+            .push(label(format!(
+                "Ducking Threshold: {:.0} dB",
+                self.ducking.threshold()
+            )))
+            .push(ducking_threshold)
+            .push(label(format!(
+                "Ducking Amount: {:.0} dB",
+                self.ducking.amount()
+            )))
+            .push(ducking_amount)
+            .push(save_button)
+            .into();
+
+        let content: Element<_> = Scrollable::new(&mut self.scroll).push(content).into();
+
+        #[cfg(feature = "debug")]
+        let content = content.explain(iced::Color::BLACK);
+
+        Container::new(content)
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .padding(0)
+            .center_x()
+            .into()
+    }
+}
+
+impl App {
+    pub fn refresh(&mut self) -> () {
+        let setting = crate::SETTINGS.read().unwrap();
+        self.processing = setting.media.mic_processing.clone();
+        self.ducking = setting.media.ducking.clone();
+    }
+}
@@ -1,28 +1,66 @@
 use iced::{
-    alignment, button, pick_list, text_input, Button, Checkbox, Column, Command, Container,
-    Element, Length, PickList, Space, Subscription, Text, TextInput,
+    alignment, button, pick_list, scrollable, text_input, time, Button, Checkbox, Column, Command,
+    Container, Element, Length, PickList, Scrollable, Space, Subscription, Text, TextInput,
 };
 
+use std::time::{Duration, Instant};
+
+use crate::device::{CaptureDevice, DeviceKind};
 use crate::ingest::Service;
+use crate::setting::PipPosition;
+use crate::view::control;
 use crate::View;
 
+const DELAY_LABELS: [&str; 3] = [
+    "HDMI Audio Delay (ms)",
+    "Microphone Delay (ms)",
+    "Video Delay (ms)",
+];
+
 #[derive(Debug, Clone)]
 pub enum Message {
     ToggleSecureInput(bool),
     InputChanged(String),
     SelectIngestService(Service),
+    SelectCameraPosition(PipPosition),
+    SelectHdmiDevice(CaptureDevice),
+    SelectCameraDevice(CaptureDevice),
+    SelectHdmiAudioDevice(CaptureDevice),
+    SelectMicDevice(CaptureDevice),
+    RefreshDevices(Instant),
+    TextChanged(usize, String),
+    DelayChanged(usize, String),
     UpdateSetting,
 }
 
 #[derive(Default)]
 pub struct App {
     back: button::State,
+    microphone: button::State,
+    scroll: scrollable::State,
     select_service: pick_list::State<Service>,
+    select_position: pick_list::State<PipPosition>,
+    select_hdmi: pick_list::State<CaptureDevice>,
+    select_camera: pick_list::State<CaptureDevice>,
+    select_hdmi_audio: pick_list::State<CaptureDevice>,
+    select_mic: pick_list::State<CaptureDevice>,
     input_url: text_input::State,
     input_key: text_input::State,
     ingest_service: Option<Service>,
     custom_url: String,
     stream_key: String,
+    camera_position: Option<PipPosition>,
+    video_devices: Vec<CaptureDevice>,
+    audio_devices: Vec<CaptureDevice>,
+    hdmi_device: Option<CaptureDevice>,
+    camera_device: Option<CaptureDevice>,
+    hdmi_audio_device: Option<CaptureDevice>,
+    mic_device: Option<CaptureDevice>,
+    input_texts: Vec<text_input::State>,
+    text_names: Vec<String>,
+    texts: Vec<String>,
+    input_delays: [text_input::State; 3],
+    delays: [String; 3],
     is_secure: bool,
 }
 
@@ -39,7 +77,7 @@ impl super::ViewApp for App {
     }
 
     fn subscription(&self) -> Subscription<Self::LocalMessage> {
-        Subscription::none()
+        time::every(Duration::from_secs(2)).map(Message::RefreshDevices)
     }
 
     fn update(&mut self, message: Self::LocalMessage) -> Command<crate::Message> {
@@ -59,6 +97,30 @@ impl super::ViewApp for App {
                 (*setting).broadcast.ingest_service = self.ingest_service.clone();
                 (*setting).broadcast.custom_url = self.custom_url.clone();
                 (*setting).broadcast.stream_key = self.stream_key.clone();
+                (*setting).camera.position = self.camera_position;
+                (*setting).device.hdmi_device =
+                    self.hdmi_device.as_ref().map(|device| device.id.clone());
+                (*setting).device.camera_device =
+                    self.camera_device.as_ref().map(|device| device.id.clone());
+                let audio_id = |device: &Option<CaptureDevice>| {
+                    device
+                        .as_ref()
+                        .filter(|device| !device.id.is_empty())
+                        .map(|device| device.id.clone())
+                };
+                (*setting).device.hdmi_audio_device = audio_id(&self.hdmi_audio_device);
+                (*setting).device.mic_device = audio_id(&self.mic_device);
+                for (text, value) in (*setting).texts.iter_mut().zip(self.texts.iter()) {
+                    // File backed texts are picked up by the watcher in the pipeline
+                    match &text.file {
+                        Some(file) => {
+                            if let Err(err) = std::fs::write(file, value) {
+                                println!("Write text to {} failed: {}", file, err);
+                            }
+                        }
+                        None => text.text = Some(value.clone()),
+                    }
+                }
 
                 if let Err(err) = setting.save() {
                     println!("Save setting failed: {}", err);
@@ -68,6 +130,50 @@ impl super::ViewApp for App {
             Message::SelectIngestService(ingest) => {
                 self.ingest_service = Some(ingest);
             }
+            Message::SelectCameraPosition(position) => {
+                self.camera_position = Some(position);
+            }
+            Message::SelectHdmiDevice(device) => {
+                self.hdmi_device = Some(device);
+            }
+            Message::SelectCameraDevice(device) => {
+                self.camera_device = Some(device);
+            }
+            Message::RefreshDevices(_) => {
+                self.refresh_devices();
+            }
+            Message::SelectHdmiAudioDevice(device) => {
+                self.hdmi_audio_device = Some(device);
+            }
+            Message::SelectMicDevice(device) => {
+                self.mic_device = Some(device);
+            }
+            Message::DelayChanged(index, changed) => {
+                let delay = if changed.is_empty() {
+                    Some(0)
+                } else {
+                    changed.parse::<i64>().ok()
+                };
+                self.delays[index] = changed;
+
+                // Apply right away so that lip sync can be dialed in by ear
+                if let Some(delay) = delay {
+                    {
+                        let mut setting = crate::SETTINGS.write().unwrap();
+                        match index {
+                            0 => (*setting).media.hdmi_delay = Some(delay),
+                            1 => (*setting).media.mic_delay = Some(delay),
+                            _ => (*setting).media.video_delay = Some(delay),
+                        }
+                    }
+                    return Command::perform(async {}, |_| control::Message::UpdateSync.into());
+                }
+            }
+            Message::TextChanged(index, changed) => {
+                if let Some(text) = self.texts.get_mut(index) {
+                    *text = changed;
+                }
+            }
         }
         Command::none()
     }
@@ -131,10 +237,139 @@ impl super::ViewApp for App {
         })
         .width(Length::Fill);
 
+        let position_label = Text::new("Camera Position")
+            .size(20)
+            .horizontal_alignment(alignment::Horizontal::Left)
+            .width(Length::Fill);
+
+        let select_position = PickList::new(
+            &mut self.select_position,
+            &PipPosition::ALL[..],
+            self.camera_position,
+            |event| Message::SelectCameraPosition(event).into(),
+        )
+        .placeholder("Choose Camera Position...")
+        .padding(10)
+        .width(Length::Fill);
+
+        let hdmi_label = Text::new("HDMI Device")
+            .size(20)
+            .horizontal_alignment(alignment::Horizontal::Left)
+            .width(Length::Fill);
+
+        let select_hdmi = PickList::new(
+            &mut self.select_hdmi,
+            &self.video_devices[..],
+            self.hdmi_device.clone(),
+            |event| Message::SelectHdmiDevice(event).into(),
+        )
+        .placeholder("Default")
+        .padding(10)
+        .width(Length::Fill);
+
+        let camera_label = Text::new("Camera Device")
+            .size(20)
+            .horizontal_alignment(alignment::Horizontal::Left)
+            .width(Length::Fill);
+
+        let select_camera = PickList::new(
+            &mut self.select_camera,
+            &self.video_devices[..],
+            self.camera_device.clone(),
+            |event| Message::SelectCameraDevice(event).into(),
+        )
+        .placeholder("Default")
+        .padding(10)
+        .width(Length::Fill);
+
+        let hdmi_audio_label = Text::new("HDMI Audio Device")
+            .size(20)
+            .horizontal_alignment(alignment::Horizontal::Left)
+            .width(Length::Fill);
+
+        let select_hdmi_audio = PickList::new(
+            &mut self.select_hdmi_audio,
+            &self.audio_devices[..],
+            self.hdmi_audio_device.clone(),
+            |event| Message::SelectHdmiAudioDevice(event).into(),
+        )
+        .placeholder("Default")
+        .padding(10)
+        .width(Length::Fill);
+
+        let mic_label = Text::new("Microphone Device")
+            .size(20)
+            .horizontal_alignment(alignment::Horizontal::Left)
+            .width(Length::Fill);
+
+        let select_mic = PickList::new(
+            &mut self.select_mic,
+            &self.audio_devices[..],
+            self.mic_device.clone(),
+            |event| Message::SelectMicDevice(event).into(),
+        )
+        .placeholder("Default")
+        .padding(10)
+        .width(Length::Fill);
+
+        let texts = self
+            .input_texts
+            .iter_mut()
+            .zip(self.text_names.iter().zip(self.texts.iter()))
+            .enumerate()
+            .fold(
+                Column::new().spacing(10),
+                |column, (index, (state, (name, text)))| {
+                    column
+                        .push(
+                            Text::new(name)
+                                .size(20)
+                                .horizontal_alignment(alignment::Horizontal::Left)
+                                .width(Length::Fill),
+                        )
+                        .push(
+                            TextInput::new(state, "", text, move |event| {
+                                Message::TextChanged(index, event).into()
+                            })
+                            .padding(10)
+                            .size(30),
+                        )
+                },
+            );
+
+        let delays = self
+            .input_delays
+            .iter_mut()
+            .zip(self.delays.iter())
+            .enumerate()
+            .fold(
+                Column::new().spacing(10),
+                |column, (index, (state, delay))| {
+                    column
+                        .push(
+                            Text::new(DELAY_LABELS[index])
+                                .size(20)
+                                .horizontal_alignment(alignment::Horizontal::Left)
+                                .width(Length::Fill),
+                        )
+                        .push(
+                            TextInput::new(state, "0", delay, move |event| {
+                                Message::DelayChanged(index, event).into()
+                            })
+                            .padding(10)
+                            .size(30),
+                        )
+                },
+            );
+
         let save_button = Button::new(&mut self.back, Text::new("Save"))
             .padding(10)
             .on_press(Message::UpdateSetting.into());
 
+        let microphone_button = Button::new(&mut self.microphone, Text::new("Microphone"))
+            .padding(10)
+            .on_press(crate::Message::ChangeView(View::Microphone));
+
         let content: Element<_> = Column::new()
             .spacing(20)
             .padding(20)
@@ -147,9 +382,24 @@ impl super::ViewApp for App {
             .push(key_label)
             .push(key_input)
             .push(checkbox)
+            .push(position_label)
+            .push(select_position)
+            .push(hdmi_label)
+            .push(select_hdmi)
+            .push(camera_label)
+            .push(select_camera)
+            .push(hdmi_audio_label)
+            .push(select_hdmi_audio)
+            .push(mic_label)
+            .push(select_mic)
+            .push(texts)
+            .push(delays)
+            .push(microphone_button)
             .push(save_button)
             .into();
 
+        let content: Element<_> = Scrollable::new(&mut self.scroll).push(content).into();
+
         #[cfg(feature = "debug")]
         let content = content.explain(iced::Color::BLACK);
 
@@ -168,5 +418,46 @@ impl App {
         self.custom_url = setting.broadcast.custom_url.clone();
         self.stream_key = setting.broadcast.stream_key.clone();
         self.ingest_service = setting.broadcast.ingest_service;
+        self.camera_position = setting.camera.position;
+        self.refresh_devices();
+        let find_device = |devices: &Vec<CaptureDevice>, id: &Option<String>| {
+            id.as_ref().map(|id| {
+                devices
+                    .iter()
+                    .find(|device| &device.id == id)
+                    .cloned()
+                    .unwrap_or_else(|| CaptureDevice::from_id(id))
+            })
+        };
+        self.hdmi_device = find_device(&self.video_devices, &setting.device.hdmi_device);
+        self.camera_device = find_device(&self.video_devices, &setting.device.camera_device);
+        self.hdmi_audio_device =
+            find_device(&self.audio_devices, &setting.device.hdmi_audio_device);
+        self.mic_device = find_device(&self.audio_devices, &setting.device.mic_device);
+        self.text_names = setting.texts.iter().map(|text| text.name.clone()).collect();
+        self.texts = setting
+            .texts
+            .iter()
+            .map(|text| match &text.file {
+                Some(file) => std::fs::read_to_string(file)
+                    .map(|text| text.trim_end().to_string())
+                    .unwrap_or_default(),
+                None => text.text.clone().unwrap_or_default(),
+            })
+            .collect();
+        self.delays = [
+            setting.media.hdmi_delay,
+            setting.media.mic_delay,
+            setting.media.video_delay,
+        ]
+        .map(|delay| delay.unwrap_or(0).to_string());
+        self.input_texts = vec![text_input::State::default(); self.texts.len()];
+    }
+
+    fn refresh_devices(&mut self) {
+        self.video_devices = CaptureDevice::list(DeviceKind::Video);
+        self.audio_devices = std::iter::once(CaptureDevice::default_audio())
+            .chain(CaptureDevice::list(DeviceKind::Audio))
+            .collect();
     }
 }
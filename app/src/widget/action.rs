@@ -3,6 +3,7 @@ use iced::{alignment, button, Background, Button, Color, Length, Text};
 
 pub enum IconButton {
     Round,
+    Flat,
 }
 
 impl button::StyleSheet for IconButton {
@@ -14,6 +15,7 @@ impl button::StyleSheet for IconButton {
                 border_radius: 80.0,
                 ..button::Style::default()
             },
+            IconButton::Flat => button::Style::default(),
         }
     }
 }
@@ -1,16 +1,30 @@
 use iced_native::layout::{self, Layout};
 use iced_native::renderer;
-use iced_native::{Color, Element, Length, Point, Rectangle, Size, Widget};
+use iced_native::text;
+use iced_native::{alignment, Color, Element, Length, Point, Rectangle, Size, Widget};
+
+use crate::stream::{ChannelLevel, FLOOR};
+
+const TICKS: [f32; 8] = [0.0, -6.0, -12.0, -18.0, -24.0, -36.0, -48.0, -60.0];
+
+/// Distance of a dBFS value from the top of the meter, 0.0 at full scale and 1.0 at the floor.
+fn position(db: f32) -> f32 {
+    db.clamp(FLOOR, 0.0) / FLOOR
+}
 
 pub struct LevelMeter {
-    level: f32,
+    level: ChannelLevel,
+    warning: f32,
+    clip: f32,
     height: Length,
 }
 
 impl LevelMeter {
-    pub fn new(level: f32) -> Self {
+    pub fn new(level: ChannelLevel) -> Self {
         Self {
             level,
+            warning: -18.0,
+            clip: -3.0,
             height: Length::Shrink,
         }
     }
@@ -19,6 +33,13 @@ impl LevelMeter {
         self.height = height;
         self
     }
+
+    /// Levels in dBFS above which the meter turns yellow and red.
+    pub fn thresholds(mut self, warning: f32, clip: f32) -> Self {
+        self.warning = warning;
+        self.clip = clip;
+        self
+    }
 }
 
 impl<Message, Renderer> Widget<Message, Renderer> for LevelMeter
@@ -46,45 +67,67 @@ where
         _cursor_position: Point,
         _viewport: &Rectangle,
     ) {
-        let position = layout.position();
-        let make_quad = |offset: f32, height: f32, area_height: f32| -> renderer::Quad {
-            renderer::Quad {
-                bounds: Rectangle {
-                    x: position.x,
-                    y: position.y + offset * area_height,
-                    width: 10.0,
-                    height: height * area_height,
-                },
-                border_radius: 0.0,
-                border_width: 0.0,
-                border_color: Color::TRANSPARENT,
+        let bounds = layout.bounds();
+        let mut fill = |top: f32, bottom: f32, color: Color| {
+            let y = position(top);
+            let height = position(bottom) - y;
+            if height <= 0.0 {
+                return;
             }
+            renderer.fill_quad(
+                renderer::Quad {
+                    bounds: Rectangle {
+                        x: bounds.x,
+                        y: bounds.y + y * bounds.height,
+                        width: bounds.width,
+                        height: height * bounds.height,
+                    },
+                    border_radius: 0.0,
+                    border_width: 0.0,
+                    border_color: Color::TRANSPARENT,
+                },
+                color,
+            );
         };
 
-        let level = self.level.min(1.0);
-
-        let height = layout.bounds().height;
-        renderer.fill_quad(make_quad(0.0, 0.3, height), Color::from_rgb8(192, 0, 0));
-        if level > 0.85 {
-            let h = level - 0.85;
-            let offset = 0.15 - h;
-            renderer.fill_quad(make_quad(offset, h, height), Color::from_rgb8(255, 0, 0));
-            renderer.fill_quad(make_quad(0.15, 0.20, height), Color::from_rgb8(255, 255, 0));
-            renderer.fill_quad(make_quad(0.35, 0.65, height), Color::from_rgb8(0, 255, 0));
-        } else {
-            renderer.fill_quad(make_quad(0.15, 0.20, height), Color::from_rgb8(192, 192, 0));
-            if level > 0.65 {
-                let h = level - 0.65;
-                let offset = 0.35 - h;
-                renderer.fill_quad(make_quad(offset, h, height), Color::from_rgb8(255, 255, 0));
-                renderer.fill_quad(make_quad(0.35, 0.65, height), Color::from_rgb8(0, 255, 0));
-            } else {
-                renderer.fill_quad(make_quad(0.35, 0.65, height), Color::from_rgb8(0, 192, 0));
-                let h = level;
-                let offset = 1. - h;
-                renderer.fill_quad(make_quad(offset, h, height), Color::from_rgb8(0, 255, 0));
+        // Zones from the bottom up with their background, peak and RMS colors
+        let zones = [
+            (FLOOR, self.warning, [(0, 96, 0), (0, 192, 0), (0, 255, 0)]),
+            (
+                self.warning,
+                self.clip,
+                [(96, 96, 0), (192, 192, 0), (255, 255, 0)],
+            ),
+            (self.clip, 0.0, [(96, 0, 0), (192, 0, 0), (255, 0, 0)]),
+        ];
+        let color = |(r, g, b): (u8, u8, u8)| Color::from_rgb8(r, g, b);
+        for (bottom, top, [background, peak, rms]) in zones {
+            fill(top, bottom, color(background));
+            if self.level.peak > bottom {
+                fill(self.level.peak.min(top), bottom, color(peak));
+            }
+            if self.level.rms > bottom {
+                fill(self.level.rms.min(top), bottom, color(rms));
             }
         }
+
+        if self.level.hold > FLOOR {
+            let hold = position(self.level.hold) * bounds.height;
+            renderer.fill_quad(
+                renderer::Quad {
+                    bounds: Rectangle {
+                        x: bounds.x,
+                        y: bounds.y + (hold - 1.0).max(0.0),
+                        width: bounds.width,
+                        height: 2.0,
+                    },
+                    border_radius: 0.0,
+                    border_width: 0.0,
+                    border_color: Color::TRANSPARENT,
+                },
+                Color::WHITE,
+            );
+        }
     }
 }
 
@@ -96,3 +139,69 @@ where
         Element::new(self)
     }
 }
+
+/// dBFS tick labels to put beside level meters of the same height.
+pub struct MeterScale {
+    height: Length,
+}
+
+impl MeterScale {
+    pub fn new(height: Length) -> Self {
+        Self { height }
+    }
+}
+
+impl<Message, Renderer> Widget<Message, Renderer> for MeterScale
+where
+    Renderer: text::Renderer,
+{
+    fn width(&self) -> Length {
+        Length::Shrink
+    }
+
+    fn height(&self) -> Length {
+        self.height
+    }
+
+    fn layout(&self, _renderer: &Renderer, limits: &layout::Limits) -> layout::Node {
+        let limits = limits.height(self.height);
+        layout::Node::new(Size::new(24.0, limits.max().height))
+    }
+
+    fn draw(
+        &self,
+        renderer: &mut Renderer,
+        _style: &renderer::Style,
+        layout: Layout<'_>,
+        _cursor_position: Point,
+        _viewport: &Rectangle,
+    ) {
+        let bounds = layout.bounds();
+        for db in TICKS {
+            let content = format!("{}", db);
+            renderer.fill_text(text::Text {
+                content: &content,
+                bounds: Rectangle {
+                    x: bounds.x + bounds.width,
+                    y: bounds.y + position(db) * bounds.height,
+                    width: bounds.width,
+                    height: 12.0,
+                },
+                size: 12.0,
+                color: Color::WHITE,
+                font: Default::default(),
+                horizontal_alignment: alignment::Horizontal::Right,
+                vertical_alignment: alignment::Vertical::Center,
+            });
+        }
+    }
+}
+
+impl<'a, Message, Renderer> Into<Element<'a, Message, Renderer>> for MeterScale
+where
+    Renderer: text::Renderer,
+{
+    fn into(self) -> Element<'a, Message, Renderer> {
+        Element::new(self)
+    }
+}